@@ -32,17 +32,38 @@ fn main() -> Result<(), Box<dyn Error>> {
     println!("cargo:rerun-if-changed=src/trampoline.S");
     println!("cargo:rerun-if-changed=src/switch.S");
 
+    // timer interval in CLINT cycles, set RRXV6_TIMER_INTERVAL to change the tick rate
+    let interval = match env::var("RRXV6_TIMER_INTERVAL") {
+        Ok(s) => s.trim().parse::<u64>()?,
+        Err(_) => 1000000,
+    };
+    if interval == 0 {
+        return Err("RRXV6_TIMER_INTERVAL must not be 0".into());
+    }
+    let mut f = File::create(out_dir.join("interval.rs"))?;
+    writeln!(f, "/// Timer interrupt interval in CLINT cycles")?;
+    writeln!(f, "pub const TIMER_INTERVAL: u64 = {};", interval)?;
+    println!("cargo:rerun-if-env-changed=RRXV6_TIMER_INTERVAL");
+
+    // the first user program, set RRXV6_INIT to run another one such as user/usertests
+    let init = env::var("RRXV6_INIT").unwrap_or_else(|_| "user/initcode".to_string());
+    println!("cargo:rerun-if-env-changed=RRXV6_INIT");
+    println!("cargo:rerun-if-changed={}", init);
+
     // write byte data of initcode to file
     let dest_path = Path::new(&out_dir).join("initcode.rs");
     let buf = Command::new("stat")
-        .args(&["-c", "%s", "user/initcode"])
-        .output().unwrap();
+        .args(&["-c", "%s", &init])
+        .output()
+        .unwrap();
     let len = String::from_utf8_lossy(&buf.stdout)
         .trim()
-        .parse::<u32>().unwrap();
+        .parse::<u32>()
+        .unwrap();
     let buf = Command::new("xxd")
-        .args(&["-p", "-c", "1", "user/initcode"])
-        .output().unwrap();
+        .args(&["-p", "-c", "1", &init])
+        .output()
+        .unwrap();
     let out = String::from_utf8_lossy(&buf.stdout)
         .trim()
         .split('\n')
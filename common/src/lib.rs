@@ -0,0 +1,11 @@
+//! Architecture independent parts of rrxv6.
+//! The kernel uses them through `crate::vm` and `crate::list`,
+//! and they build for the host so `cargo test` can run their unit tests.
+
+#![cfg_attr(not(test), no_std)]
+#![feature(try_trait_v2)]
+
+extern crate alloc;
+
+pub mod list;
+pub mod vm;
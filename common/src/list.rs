@@ -0,0 +1,215 @@
+//! list struct that are used in scheduler
+
+use alloc::boxed::Box;
+use core::ptr;
+
+type Link<T> = *mut Node<T>;
+
+pub struct List<T> {
+    head: Link<T>,
+    tail: *mut Node<T>,
+}
+
+struct Node<T> {
+    elem: T,
+    next: Link<T>,
+}
+
+pub struct IterMut<'a, T> {
+    next: Option<&'a mut Node<T>>,
+}
+
+impl<T> List<T> {
+    pub const fn new() -> Self {
+        List {
+            head: ptr::null_mut(),
+            tail: ptr::null_mut(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.is_null()
+    }
+
+    pub fn push(&mut self, elem: T) {
+        unsafe {
+            let new_tail = Box::into_raw(Box::new(Node {
+                elem,
+                next: ptr::null_mut(),
+            }));
+
+            if self.is_empty() {
+                self.head = new_tail;
+            } else {
+                (*self.tail).next = new_tail;
+            }
+            self.tail = new_tail;
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        unsafe {
+            if self.is_empty() {
+                None
+            } else {
+                let head = Box::from_raw(self.head);
+                self.head = head.next;
+
+                if self.is_empty() {
+                    self.tail = ptr::null_mut();
+                }
+
+                Some(head.elem)
+            }
+        }
+    }
+
+    pub fn peek(&self) -> Option<&T> {
+        unsafe { self.head.as_ref().map(|node| &node.elem) }
+    }
+
+    pub fn peek_mut(&mut self) -> Option<&mut T> {
+        unsafe { self.head.as_mut().map(|node| &mut node.elem) }
+    }
+
+    /// Remove the elements matching pred into a new list, keeping their order.
+    /// Nodes are moved without allocation.
+    pub fn take_if(&mut self, mut pred: impl FnMut(&T) -> bool) -> List<T> {
+        let mut taken = List::new();
+        unsafe {
+            let mut prev: Link<T> = ptr::null_mut();
+            let mut node = self.head;
+            while !node.is_null() {
+                let next = (*node).next;
+                if pred(&(*node).elem) {
+                    // unlink node from self
+                    if prev.is_null() {
+                        self.head = next;
+                    } else {
+                        (*prev).next = next;
+                    }
+                    if self.tail == node {
+                        self.tail = prev;
+                    }
+
+                    // append node to taken
+                    (*node).next = ptr::null_mut();
+                    if taken.is_empty() {
+                        taken.head = node;
+                    } else {
+                        (*taken.tail).next = node;
+                    }
+                    taken.tail = node;
+                } else {
+                    prev = node;
+                }
+                node = next;
+            }
+        }
+        taken
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, T> {
+        unsafe {
+            IterMut {
+                next: self.head.as_mut(),
+            }
+        }
+    }
+}
+
+impl<'a, T> Iterator for IterMut<'a, T> {
+    type Item = &'a mut T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next.take().map(|node| {
+            self.next = unsafe { node.next.as_mut() };
+            &mut node.elem
+        })
+    }
+}
+
+impl<T> Default for List<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for List<T> {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::List;
+
+    #[test]
+    fn push_pop() {
+        let mut list = List::new();
+        assert!(list.is_empty());
+        assert_eq!(list.pop(), None::<i32>);
+
+        list.push(1);
+        list.push(2);
+        list.push(3);
+        assert_eq!(list.peek(), Some(&1));
+        assert_eq!(list.pop(), Some(1));
+        assert_eq!(list.pop(), Some(2));
+
+        // push after the list drains to one element keeps the tail right
+        list.push(4);
+        assert_eq!(list.pop(), Some(3));
+        assert_eq!(list.pop(), Some(4));
+        assert_eq!(list.pop(), None);
+        assert!(list.is_empty());
+
+        list.push(5);
+        assert_eq!(list.peek(), Some(&5));
+    }
+
+    #[test]
+    fn peek_mut() {
+        let mut list = List::new();
+        list.push(1);
+        if let Some(elem) = list.peek_mut() {
+            *elem = 10;
+        }
+        assert_eq!(list.pop(), Some(10));
+    }
+
+    #[test]
+    fn iter_mut() {
+        let mut list = List::new();
+        for i in 0..4 {
+            list.push(i);
+        }
+        list.iter_mut().for_each(|elem| *elem *= 2);
+        for i in 0..4 {
+            assert_eq!(list.pop(), Some(i * 2));
+        }
+    }
+
+    #[test]
+    fn take_if() {
+        let mut list = List::new();
+        for i in 0..6 {
+            list.push(i);
+        }
+        let mut even = list.take_if(|elem| elem % 2 == 0);
+        for i in [0, 2, 4] {
+            assert_eq!(even.pop(), Some(i));
+        }
+        assert!(even.is_empty());
+
+        // the tail of list is still valid after its last element is taken
+        let mut last = list.take_if(|elem| *elem == 5);
+        assert_eq!(last.pop(), Some(5));
+        list.push(7);
+        for i in [1, 3, 7] {
+            assert_eq!(list.pop(), Some(i));
+        }
+        assert!(list.is_empty());
+    }
+}
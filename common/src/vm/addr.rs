@@ -0,0 +1,449 @@
+use super::page_table::{PageTableIndex, PageTableLevel};
+use bit_field::BitField;
+
+use core::ops::{Add, AddAssign, Sub, SubAssign};
+
+/// Number of virtual address bits translated by the paging mode
+#[cfg(not(feature = "sv48"))]
+pub const VA_BITS: u32 = 39;
+#[cfg(feature = "sv48")]
+pub const VA_BITS: u32 = 48;
+
+#[derive(Debug, Clone)]
+pub struct InvalidVirtAddr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct VirtAddr(u64);
+
+/// A 64-bits physical memory address.
+///
+/// A wrapper type around `u64`
+/// On riscv, only lower 56 bits can be used, top 8 bits must be zeroed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PhysAddr(u64);
+
+impl VirtAddr {
+    #[inline]
+    pub fn new(addr: u64) -> Self {
+        Self::try_new(addr).unwrap_or_else(|_| {
+            panic!(
+                "Virtual address in riscv should have bit {}-63 copied from bit {} {}",
+                VA_BITS,
+                VA_BITS - 1,
+                addr
+            )
+        })
+    }
+
+    /// Try to create a new virtual address.
+    #[inline]
+    pub fn try_new(addr: u64) -> Result<VirtAddr, InvalidVirtAddr> {
+        // bit VA_BITS-1 and the bits above it
+        let sign_bits = (1 << (65 - VA_BITS)) - 1;
+        match addr.get_bits(VA_BITS as usize - 1..64) {
+            0 => Ok(VirtAddr(addr)),                   // valid address
+            x if x == sign_bits => Ok(VirtAddr(addr)), // valid address
+            1 => Ok(VirtAddr::new_truncate(addr)),     // address need sign extend
+            _ => Err(InvalidVirtAddr {}),
+        }
+    }
+
+    /// Create a VirtAddr with signed extension
+    #[inline]
+    pub fn new_truncate(addr: u64) -> Self {
+        let shift = 64 - VA_BITS;
+        Self(((addr << shift) as i64 >> shift) as u64)
+    }
+
+    #[inline]
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+
+    #[inline]
+    pub fn is_align(self) -> bool {
+        self.0 & (4096 - 1) == 0
+    }
+
+    #[inline]
+    pub fn align_down(self) -> Self {
+        Self(align_down(self.0, 4096))
+    }
+
+    #[inline]
+    pub fn align_up(self) -> Self {
+        Self(align_up(self.0, 4096))
+    }
+
+    /// Return the 9 bits level 0 page table index from offset [12,20]
+    #[inline]
+    pub const fn p0_index(self) -> PageTableIndex {
+        PageTableIndex::new_truncate((self.0 >> 12) as u16)
+    }
+
+    /// Return the 9 bits level 1 page table index from offset [21,29]
+    #[inline]
+    pub const fn p1_index(self) -> PageTableIndex {
+        PageTableIndex::new_truncate((self.0 >> 9 >> 12) as u16)
+    }
+
+    /// Return the 9 bits level 2 page table index from offset [30,38]
+    #[inline]
+    pub const fn p2_index(self) -> PageTableIndex {
+        PageTableIndex::new_truncate((self.0 >> 9 >> 9 >> 12) as u16)
+    }
+
+    /// Return the 9 bits level 3 page table index from offset [39,47]
+    /// Only valid with sv48 mode
+    #[inline]
+    pub const fn p3_index(self) -> PageTableIndex {
+        PageTableIndex::new_truncate((self.0 >> 9 >> 9 >> 9 >> 12) as u16)
+    }
+
+    /// Return the 9 bits page table index according to level
+    #[inline]
+    pub const fn get_index(self, level: PageTableLevel) -> PageTableIndex {
+        match level {
+            PageTableLevel::Zero => self.p0_index(),
+            PageTableLevel::One => self.p1_index(),
+            PageTableLevel::Two => self.p2_index(),
+            PageTableLevel::Three => self.p3_index(),
+        }
+    }
+}
+
+impl Add<u64> for VirtAddr {
+    type Output = Self;
+
+    #[inline]
+    fn add(self, rhs: u64) -> Self::Output {
+        VirtAddr::new(self.0 + rhs)
+    }
+}
+
+impl AddAssign<u64> for VirtAddr {
+    #[inline]
+    fn add_assign(&mut self, rhs: u64) {
+        *self = *self + rhs;
+    }
+}
+
+impl Add<usize> for VirtAddr {
+    type Output = Self;
+
+    #[inline]
+    fn add(self, rhs: usize) -> Self::Output {
+        self + rhs as u64
+    }
+}
+
+impl AddAssign<usize> for VirtAddr {
+    #[inline]
+    fn add_assign(&mut self, rhs: usize) {
+        self.add_assign(rhs as u64)
+    }
+}
+
+impl Sub<u64> for VirtAddr {
+    type Output = Self;
+
+    #[inline]
+    fn sub(self, rhs: u64) -> Self::Output {
+        VirtAddr::new(self.0 - rhs)
+    }
+}
+
+impl SubAssign<u64> for VirtAddr {
+    #[inline]
+    fn sub_assign(&mut self, rhs: u64) {
+        *self = *self - rhs;
+    }
+}
+
+impl Sub<usize> for VirtAddr {
+    type Output = Self;
+
+    #[inline]
+    fn sub(self, rhs: usize) -> Self::Output {
+        self - rhs as u64
+    }
+}
+
+impl SubAssign<usize> for VirtAddr {
+    #[inline]
+    fn sub_assign(&mut self, rhs: usize) {
+        self.sub_assign(rhs as u64);
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidPhysAddr;
+
+impl PhysAddr {
+    #[inline]
+    pub fn new(addr: u64) -> Self {
+        Self::try_new(addr).unwrap_or_else(|_| {
+            panic!(
+                "Physical address in riscv should have bit 56-63 zeroed {}",
+                addr
+            )
+        })
+    }
+
+    /// Try to create a new physical address.
+    #[inline]
+    pub fn try_new(addr: u64) -> Result<PhysAddr, InvalidPhysAddr> {
+        match addr.get_bits(56..64) {
+            0 => Ok(PhysAddr(addr)), // valid address
+            _ => Err(InvalidPhysAddr {}),
+        }
+    }
+
+    /// Create a PhysAddr with zeroed bit 56-64
+    #[inline]
+    pub fn new_truncate(addr: u64) -> Self {
+        Self(addr & ((1 << 56) - 1))
+    }
+
+    #[inline]
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+
+    #[inline]
+    pub fn as_pte(self) -> u64 {
+        (self.0 >> 12) << 10
+    }
+
+    #[inline]
+    pub fn align_down(self) -> Self {
+        Self(align_down(self.0, 4096))
+    }
+
+    #[inline]
+    pub fn align_up(self) -> Self {
+        Self(align_up(self.0, 4096))
+    }
+}
+
+impl Add<u64> for PhysAddr {
+    type Output = Self;
+
+    #[inline]
+    fn add(self, rhs: u64) -> Self::Output {
+        PhysAddr::new(self.0 + rhs)
+    }
+}
+
+impl AddAssign<u64> for PhysAddr {
+    #[inline]
+    fn add_assign(&mut self, rhs: u64) {
+        *self = *self + rhs;
+    }
+}
+
+impl Add<usize> for PhysAddr {
+    type Output = Self;
+
+    #[inline]
+    fn add(self, rhs: usize) -> Self::Output {
+        self + rhs as u64
+    }
+}
+
+impl AddAssign<usize> for PhysAddr {
+    #[inline]
+    fn add_assign(&mut self, rhs: usize) {
+        self.add_assign(rhs as u64)
+    }
+}
+
+impl Sub<u64> for PhysAddr {
+    type Output = Self;
+
+    #[inline]
+    fn sub(self, rhs: u64) -> Self::Output {
+        PhysAddr::new(self.0 - rhs)
+    }
+}
+
+impl SubAssign<u64> for PhysAddr {
+    #[inline]
+    fn sub_assign(&mut self, rhs: u64) {
+        *self = *self - rhs;
+    }
+}
+
+impl Sub<usize> for PhysAddr {
+    type Output = Self;
+
+    #[inline]
+    fn sub(self, rhs: usize) -> Self::Output {
+        self - rhs as u64
+    }
+}
+
+impl SubAssign<usize> for PhysAddr {
+    #[inline]
+    fn sub_assign(&mut self, rhs: usize) {
+        self.sub_assign(rhs as u64);
+    }
+}
+
+#[inline]
+pub const fn align_down(addr: u64, align: u64) -> u64 {
+    assert!(align.is_power_of_two());
+    addr & !(align - 1)
+}
+
+#[inline]
+pub const fn align_up(addr: u64, align: u64) -> u64 {
+    assert!(align.is_power_of_two());
+    align_down(addr + align - 1, align)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn virt_addr_canonical() {
+        assert!(VirtAddr::try_new(0).is_ok());
+        assert!(VirtAddr::try_new((1 << (VA_BITS - 1)) - 1).is_ok());
+        assert!(VirtAddr::try_new(!0 << (VA_BITS - 1)).is_ok());
+        assert!(VirtAddr::try_new(1 << VA_BITS).is_err());
+        assert!(VirtAddr::try_new(0x8000_0000_0000_0000).is_err());
+    }
+
+    #[test]
+    #[cfg(not(feature = "sv48"))]
+    fn virt_addr_sign_extend() {
+        let va = VirtAddr::try_new(1 << 38).unwrap();
+        assert_eq!(va.as_u64(), 0xffff_ffc0_0000_0000);
+        assert_eq!(VirtAddr::new_truncate(1 << 38), va);
+        // canonical in sv48 only
+        assert!(VirtAddr::try_new(1 << 40).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "sv48")]
+    fn virt_addr_sign_extend() {
+        let va = VirtAddr::try_new(1 << 47).unwrap();
+        assert_eq!(va.as_u64(), 0xffff_8000_0000_0000);
+        assert_eq!(VirtAddr::new_truncate(1 << 47), va);
+        assert!(VirtAddr::try_new(1 << 40).is_ok());
+    }
+
+    #[test]
+    fn virt_addr_align() {
+        let va = VirtAddr::new(0x1234);
+        assert!(!va.is_align());
+        assert_eq!(va.align_down(), VirtAddr::new(0x1000));
+        assert_eq!(va.align_up(), VirtAddr::new(0x2000));
+        assert!(VirtAddr::new(0x2000).is_align());
+        assert_eq!(VirtAddr::new(0x2000).align_up(), VirtAddr::new(0x2000));
+    }
+
+    #[test]
+    fn virt_addr_index() {
+        let va = VirtAddr::new((3 << 30) | (2 << 21) | (1 << 12) | 0xabc);
+        assert_eq!(va.p0_index(), PageTableIndex::new(1));
+        assert_eq!(va.p1_index(), PageTableIndex::new(2));
+        assert_eq!(va.p2_index(), PageTableIndex::new(3));
+        assert_eq!(va.get_index(PageTableLevel::One), PageTableIndex::new(2));
+    }
+
+    #[test]
+    fn phys_addr() {
+        assert!(PhysAddr::try_new((1 << 56) - 1).is_ok());
+        assert!(PhysAddr::try_new(1 << 56).is_err());
+        assert_eq!(PhysAddr::new_truncate(1 << 56), PhysAddr::new(0));
+        assert_eq!(PhysAddr::new(0x8000_1000).as_pte(), 0x8000_1000 >> 2);
+        assert_eq!(
+            PhysAddr::new(0x8000_1fff).align_down(),
+            PhysAddr::new(0x8000_1000)
+        );
+    }
+
+    #[test]
+    fn align() {
+        assert_eq!(align_down(0x1fff, 0x1000), 0x1000);
+        assert_eq!(align_up(0x1001, 0x1000), 0x2000);
+        assert_eq!(align_up(0x1000, 0x1000), 0x1000);
+    }
+
+    mod prop {
+        use super::*;
+        use proptest::prelude::*;
+
+        proptest! {
+            #[test]
+            fn try_new_accepts_canonical(addr: u64) {
+                let bits = VA_BITS as usize;
+                let top = addr.get_bits(bits - 1..64);
+                let ones = (1 << (65 - bits)) - 1;
+                let result = VirtAddr::try_new(addr);
+                prop_assert_eq!(result.is_ok(), top == 0 || top == 1 || top == ones);
+                if let Ok(va) = result {
+                    // bit VA_BITS-63 are copied from bit VA_BITS-1
+                    let va = va.as_u64();
+                    prop_assert_eq!(va.get_bits(0..bits), addr.get_bits(0..bits));
+                    let sign = if va.get_bit(bits - 1) { ones >> 1 } else { 0 };
+                    prop_assert_eq!(va.get_bits(bits..64), sign);
+                }
+            }
+
+            #[test]
+            fn new_truncate_is_canonical(addr: u64) {
+                let va = VirtAddr::new_truncate(addr);
+                prop_assert!(VirtAddr::try_new(va.as_u64()).is_ok());
+                prop_assert_eq!(VirtAddr::new_truncate(va.as_u64()), va);
+            }
+
+            #[test]
+            fn phys_addr_try_new(addr: u64) {
+                prop_assert_eq!(PhysAddr::try_new(addr).is_ok(), addr < (1 << 56));
+                prop_assert_eq!(PhysAddr::new_truncate(addr).as_u64(), addr & ((1 << 56) - 1));
+            }
+
+            #[test]
+            fn align_bounds(addr in 0..u64::MAX - 4096, shift in 0..12u32) {
+                let align = 1u64 << shift;
+                let down = align_down(addr, align);
+                let up = align_up(addr, align);
+                prop_assert_eq!(down % align, 0);
+                prop_assert_eq!(up % align, 0);
+                prop_assert!(down <= addr && addr - down < align);
+                prop_assert!(up >= addr && up - addr < align);
+                prop_assert_eq!(up == down, addr % align == 0);
+            }
+
+            #[test]
+            fn virt_addr_align_bounds(addr in 0..(1u64 << 38) - 4096) {
+                let va = VirtAddr::new(addr);
+                prop_assert!(va.align_down().is_align());
+                prop_assert!(va.align_up().is_align());
+                prop_assert!(va.align_down() <= va && va <= va.align_up());
+                prop_assert_eq!(va.is_align(), va.align_down() == va);
+            }
+
+            #[test]
+            fn index_roundtrip(i2 in 0..512u64, i1 in 0..512u64, i0 in 0..512u64, offset in 0..4096u64) {
+                let va = VirtAddr::new((i2 << 30) | (i1 << 21) | (i0 << 12) | offset);
+                prop_assert_eq!(va.p2_index(), PageTableIndex::new(i2 as u16));
+                prop_assert_eq!(va.p1_index(), PageTableIndex::new(i1 as u16));
+                prop_assert_eq!(va.p0_index(), PageTableIndex::new(i0 as u16));
+                prop_assert_eq!(va.get_index(PageTableLevel::Two), va.p2_index());
+                prop_assert_eq!(va.get_index(PageTableLevel::Zero), va.p0_index());
+            }
+
+            #[test]
+            fn add_sub_roundtrip(addr in 0..(1u64 << 38), n in 0..(1u64 << 37)) {
+                let va = VirtAddr::new(addr / 2);
+                prop_assert_eq!(va + n - n, va);
+                let pa = PhysAddr::new(addr);
+                prop_assert_eq!(pa + n - n, pa);
+            }
+        }
+    }
+}
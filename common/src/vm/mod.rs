@@ -0,0 +1,4 @@
+pub mod addr;
+pub mod page_flag;
+pub mod page_table;
+pub mod page_table_walker;
@@ -0,0 +1,250 @@
+//! riscv page table
+
+use super::page_flag::PteFlag;
+use core::ops::{Index, IndexMut};
+
+// 4096 bytes / 8 bytes per entry = 512 entries
+const ENTRY_COUNT: usize = 512;
+
+/// A 9-bits index for page table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageTableIndex(u16);
+
+impl PageTableIndex {
+    /// Create a PageTableIndex from u16
+    /// Will crash if the input > 512
+    pub fn new(index: u16) -> Self {
+        assert!((index as usize) < ENTRY_COUNT);
+        Self(index)
+    }
+
+    /// Create a PageTableIndex from u16
+    /// Truncate the input if > 512
+    pub const fn new_truncate(index: u16) -> Self {
+        Self(index % ENTRY_COUNT as u16)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PageTableLevel {
+    /// Level 0, table of page
+    Zero = 0,
+    /// Level 1, table of page table
+    One,
+    /// Level 2, table of level 1 page table
+    Two,
+    /// Level 3, table of level 2 page table, only valid in sv48 mode
+    Three,
+}
+
+/// Level of the root page table, where walks start
+#[cfg(not(feature = "sv48"))]
+pub const ROOT_LEVEL: PageTableLevel = PageTableLevel::Two;
+#[cfg(feature = "sv48")]
+pub const ROOT_LEVEL: PageTableLevel = PageTableLevel::Three;
+
+impl PageTableLevel {
+    /// Return the next level
+    pub const fn next_level(self) -> Option<Self> {
+        match self {
+            PageTableLevel::Three => Some(PageTableLevel::Two),
+            PageTableLevel::Two => Some(PageTableLevel::One),
+            PageTableLevel::One => Some(PageTableLevel::Zero),
+            PageTableLevel::Zero => None,
+        }
+    }
+
+    /// Return the bytes mapped by a leaf entry at this level,
+    /// 4 KiB at level 0, 2 MiB at level 1 and 1 GiB at level 2
+    pub const fn page_size(self) -> u64 {
+        1 << (12 + 9 * self as u64)
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct PageTableEntry {
+    entry: u64,
+}
+
+impl PageTableEntry {
+    // Create empty page table entry
+    #[inline]
+    pub const fn new() -> Self {
+        Self { entry: 0 }
+    }
+
+    // true if page is zero (unused)
+    #[inline]
+    pub const fn is_unused(&self) -> bool {
+        self.entry == 0
+    }
+
+    #[inline]
+    pub fn set_unused(&mut self) {
+        self.entry = 0;
+    }
+
+    #[inline]
+    pub fn addr(&self) -> u64 {
+        (self.entry >> 10) << 12
+    }
+
+    #[inline]
+    pub fn set_addr(&mut self, addr: u64, perm: PteFlag) {
+        // TODO: check aligned here
+        self.entry = addr | perm.bits();
+    }
+
+    #[inline]
+    pub fn flag(&self) -> PteFlag {
+        PteFlag::from_bits_truncate(self.entry & 0x3FF)
+    }
+
+    /// true if the entry maps a page, a valid entry without
+    /// read, write and execute points to the next level table
+    #[inline]
+    pub fn is_leaf(&self) -> bool {
+        let flag = self.flag();
+        flag.contains(PteFlag::PTE_VALID)
+            && flag.intersects(PteFlag::PTE_READ | PteFlag::PTE_WRITE | PteFlag::PTE_EXEC)
+    }
+}
+
+#[repr(C, align(4096))]
+pub struct PageTable {
+    entries: [PageTableEntry; ENTRY_COUNT],
+}
+
+impl PageTable {
+    /// Create empty PageTable
+    #[inline]
+    pub const fn new() -> Self {
+        const EMPTY: PageTableEntry = PageTableEntry::new();
+        Self {
+            entries: [EMPTY; ENTRY_COUNT],
+        }
+    }
+}
+
+impl Default for PageTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Index<usize> for PageTable {
+    type Output = PageTableEntry;
+
+    #[inline]
+    fn index(&self, index: usize) -> &Self::Output {
+        &self.entries[index]
+    }
+}
+
+impl IndexMut<usize> for PageTable {
+    #[inline]
+    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
+        &mut self.entries[index]
+    }
+}
+
+impl Index<PageTableIndex> for PageTable {
+    type Output = PageTableEntry;
+
+    #[inline]
+    fn index(&self, index: PageTableIndex) -> &Self::Output {
+        &self.entries[usize::from(index.0)]
+    }
+}
+
+impl IndexMut<PageTableIndex> for PageTable {
+    #[inline]
+    fn index_mut(&mut self, index: PageTableIndex) -> &mut Self::Output {
+        &mut self.entries[usize::from(index.0)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::mem::{align_of, size_of};
+
+    #[test]
+    fn page_table_layout() {
+        assert_eq!(size_of::<PageTable>(), 4096);
+        assert_eq!(align_of::<PageTable>(), 4096);
+        assert_eq!(size_of::<PageTableEntry>(), 8);
+    }
+
+    #[test]
+    fn entry() {
+        let mut pte = PageTableEntry::new();
+        assert!(pte.is_unused());
+        assert_eq!(pte.flag(), PteFlag::empty());
+
+        let perm = PteFlag::PTE_VALID | PteFlag::PTE_READ | PteFlag::PTE_USER;
+        pte.set_addr(0x8000_1000 >> 2, perm);
+        assert!(!pte.is_unused());
+        assert_eq!(pte.addr(), 0x8000_1000);
+        assert_eq!(pte.flag(), perm);
+
+        pte.set_unused();
+        assert!(pte.is_unused());
+        assert_eq!(pte.addr(), 0);
+    }
+
+    #[test]
+    fn leaf() {
+        let mut pte = PageTableEntry::new();
+        assert!(!pte.is_leaf());
+        pte.set_addr(0x8000_1000 >> 2, PteFlag::PTE_VALID);
+        assert!(!pte.is_leaf());
+        pte.set_addr(0x8000_1000 >> 2, PteFlag::PTE_VALID | PteFlag::PTE_EXEC);
+        assert!(pte.is_leaf());
+        pte.set_addr(0x8000_1000 >> 2, PteFlag::PTE_READ);
+        assert!(!pte.is_leaf());
+    }
+
+    #[test]
+    fn page_size() {
+        assert_eq!(PageTableLevel::Zero.page_size(), 4096);
+        assert_eq!(PageTableLevel::One.page_size(), 2 << 20);
+        assert_eq!(PageTableLevel::Two.page_size(), 1 << 30);
+        assert_eq!(PageTableLevel::Three.page_size(), 512 << 30);
+    }
+
+    #[test]
+    fn index() {
+        let mut page_table = PageTable::new();
+        page_table[PageTableIndex::new(511)].set_addr(0x1000 >> 2, PteFlag::PTE_VALID);
+        assert_eq!(page_table[511].addr(), 0x1000);
+        assert!((0..511).all(|i| page_table[i].is_unused()));
+        assert_eq!(PageTableIndex::new_truncate(512), PageTableIndex::new(0));
+        assert_eq!(PageTableIndex::new_truncate(513), PageTableIndex::new(1));
+    }
+
+    #[test]
+    #[should_panic]
+    fn index_out_of_range() {
+        PageTableIndex::new(512);
+    }
+
+    #[test]
+    fn next_level() {
+        assert_eq!(
+            PageTableLevel::Three.next_level(),
+            Some(PageTableLevel::Two)
+        );
+        assert_eq!(PageTableLevel::Two.next_level(), Some(PageTableLevel::One));
+        assert_eq!(PageTableLevel::One.next_level(), Some(PageTableLevel::Zero));
+        assert_eq!(PageTableLevel::Zero.next_level(), None);
+    }
+
+    #[test]
+    fn root_level() {
+        #[cfg(not(feature = "sv48"))]
+        assert_eq!(ROOT_LEVEL, PageTableLevel::Two);
+        #[cfg(feature = "sv48")]
+        assert_eq!(ROOT_LEVEL, PageTableLevel::Three);
+    }
+}
@@ -0,0 +1,321 @@
+use super::addr::VirtAddr;
+use super::page_table::{PageTable, PageTableEntry, PageTableLevel};
+
+pub struct PageTableWalkerMut<'a, Extra> {
+    pub page_table: &'a mut PageTable,
+    pub va: VirtAddr,
+    pub level: PageTableLevel,
+    pub extra: Extra,
+}
+
+/// The walker calls nonleaf on each table entry from the root down,
+/// and leaf on the entry at leaf_level, or on a superpage entry
+/// found above it, with the level of the entry.
+pub trait PageTableVisitorMut {
+    type Output: core::ops::Try;
+    fn is_valid_va(&self, va: VirtAddr) -> bool;
+    fn leaf(&mut self, pte: &mut PageTableEntry, level: PageTableLevel) -> Self::Output;
+    fn nonleaf(&mut self, pte: &mut PageTableEntry) -> Self::Output;
+    /// The level to stop at, level 1 and 2 map superpages
+    fn leaf_level(&self) -> PageTableLevel {
+        PageTableLevel::Zero
+    }
+}
+
+impl<'a, Extra: PageTableVisitorMut> PageTableWalkerMut<'a, Extra> {
+    pub fn new(
+        page_table: &'a mut PageTable,
+        va: VirtAddr,
+        level: PageTableLevel,
+        extra: Extra,
+    ) -> Option<Self> {
+        extra.is_valid_va(va).then(move || Self {
+            page_table,
+            va,
+            level,
+            extra,
+        })
+    }
+    pub fn visit_mut(&mut self) -> Extra::Output {
+        let index = self.va.get_index(self.level);
+        let pte = &mut self.page_table[index];
+
+        match self.level.next_level() {
+            Some(_) if self.level == self.extra.leaf_level() || pte.is_leaf() => {
+                self.extra.leaf(pte, self.level)
+            }
+            None => self.extra.leaf(pte, self.level),
+            Some(next_level) => {
+                let _ = self.extra.nonleaf(pte)?;
+
+                let next_table = unsafe { &mut *(pte.addr() as *mut PageTable) };
+                self.page_table = next_table;
+                self.level = next_level;
+                self.visit_mut()
+            }
+        }
+    }
+}
+
+pub struct PageTableWalker<'a, Extra> {
+    pub page_table: &'a PageTable,
+    pub va: VirtAddr,
+    pub level: PageTableLevel,
+    pub extra: Extra,
+}
+
+/// Like PageTableVisitorMut, without changing the page table
+pub trait PageTableVisitor {
+    type Output: core::ops::Try;
+    fn is_valid_va(&self, va: VirtAddr) -> bool;
+    fn leaf(&self, pte: &PageTableEntry, level: PageTableLevel) -> Self::Output;
+    fn nonleaf(&self, pte: &PageTableEntry) -> Self::Output;
+    fn leaf_level(&self) -> PageTableLevel {
+        PageTableLevel::Zero
+    }
+}
+
+impl<'a, Extra: PageTableVisitor> PageTableWalker<'a, Extra> {
+    pub fn new(
+        page_table: &'a PageTable,
+        va: VirtAddr,
+        level: PageTableLevel,
+        extra: Extra,
+    ) -> Option<Self> {
+        extra.is_valid_va(va).then(move || Self {
+            page_table,
+            va,
+            level,
+            extra,
+        })
+    }
+    pub fn visit(&mut self) -> Extra::Output {
+        let index = self.va.get_index(self.level);
+        let pte = &self.page_table[index];
+
+        match self.level.next_level() {
+            Some(_) if self.level == self.extra.leaf_level() || pte.is_leaf() => {
+                self.extra.leaf(pte, self.level)
+            }
+            None => self.extra.leaf(pte, self.level),
+            Some(next_level) => {
+                let _ = self.extra.nonleaf(pte)?;
+
+                let next_table = unsafe { &*(pte.addr() as *const PageTable) };
+                self.page_table = next_table;
+                self.level = next_level;
+                self.visit()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::addr::VA_BITS;
+    use crate::vm::page_flag::PteFlag;
+    use crate::vm::page_table::ROOT_LEVEL;
+    use alloc::boxed::Box;
+    use alloc::vec::Vec;
+    use proptest::prelude::*;
+
+    // limit of user virtual address
+    const MAXVA: u64 = 1 << (VA_BITS - 1);
+    // page tables below the root
+    const LOWER_LEVELS: usize = ROOT_LEVEL as usize;
+
+    fn pte_addr(addr: u64) -> u64 {
+        (addr >> 12) << 10
+    }
+
+    /// Map a page of level to pa, with page tables allocated on the host heap
+    struct Mapper<'a> {
+        pa: u64,
+        level: PageTableLevel,
+        tables: &'a mut Vec<Box<PageTable>>,
+    }
+
+    impl PageTableVisitorMut for Mapper<'_> {
+        type Output = Result<(), &'static str>;
+        fn is_valid_va(&self, va: VirtAddr) -> bool {
+            va < VirtAddr::new(MAXVA)
+        }
+
+        fn leaf(&mut self, pte: &mut PageTableEntry, level: PageTableLevel) -> Self::Output {
+            if !pte.is_unused() || level != self.level {
+                return Err("remap");
+            }
+            pte.set_addr(pte_addr(self.pa), PteFlag::PTE_VALID | PteFlag::PTE_READ);
+            Ok(())
+        }
+
+        fn nonleaf(&mut self, pte: &mut PageTableEntry) -> Self::Output {
+            if pte.is_unused() {
+                let table = Box::new(PageTable::new());
+                pte.set_addr(pte_addr(&*table as *const _ as u64), PteFlag::PTE_VALID);
+                self.tables.push(table);
+            }
+            Ok(())
+        }
+
+        fn leaf_level(&self) -> PageTableLevel {
+            self.level
+        }
+    }
+
+    /// Look up the physical address of va
+    struct Lookup {
+        va: u64,
+    }
+
+    impl PageTableVisitor for Lookup {
+        type Output = Option<u64>;
+        fn is_valid_va(&self, va: VirtAddr) -> bool {
+            va < VirtAddr::new(MAXVA)
+        }
+
+        fn leaf(&self, pte: &PageTableEntry, level: PageTableLevel) -> Self::Output {
+            let offset = self.va & (level.page_size() - 1) & !0xfff;
+            pte.flag()
+                .contains(PteFlag::PTE_VALID)
+                .then(|| pte.addr() + offset)
+        }
+
+        fn nonleaf(&self, pte: &PageTableEntry) -> Self::Output {
+            pte.flag().contains(PteFlag::PTE_VALID).then_some(0)
+        }
+    }
+
+    fn map_level(
+        root: &mut PageTable,
+        tables: &mut Vec<Box<PageTable>>,
+        va: u64,
+        pa: u64,
+        level: PageTableLevel,
+    ) -> Result<(), &'static str> {
+        let mapper = Mapper { pa, level, tables };
+        PageTableWalkerMut::new(root, VirtAddr::new(va), ROOT_LEVEL, mapper)
+            .ok_or("invalid va")?
+            .visit_mut()
+    }
+
+    fn map(
+        root: &mut PageTable,
+        tables: &mut Vec<Box<PageTable>>,
+        va: u64,
+        pa: u64,
+    ) -> Result<(), &'static str> {
+        map_level(root, tables, va, pa, PageTableLevel::Zero)
+    }
+
+    fn lookup(root: &PageTable, va: u64) -> Option<u64> {
+        PageTableWalker::new(root, VirtAddr::new(va), ROOT_LEVEL, Lookup { va })?.visit()
+    }
+
+    #[test]
+    fn map_and_lookup() {
+        let mut root = Box::new(PageTable::new());
+        let mut tables = Vec::new();
+
+        map(&mut root, &mut tables, 0x1000, 0x8000_0000).unwrap();
+        // one table of each level below the root
+        assert_eq!(tables.len(), LOWER_LEVELS);
+        // shares the level 0 table
+        map(&mut root, &mut tables, 0x2000, 0x8000_1000).unwrap();
+        assert_eq!(tables.len(), LOWER_LEVELS);
+        // shares the level 1 table only
+        map(&mut root, &mut tables, 0x20_0000, 0x8000_2000).unwrap();
+        assert_eq!(tables.len(), LOWER_LEVELS + 1);
+
+        assert_eq!(lookup(&root, 0x1000), Some(0x8000_0000));
+        assert_eq!(lookup(&root, 0x2000), Some(0x8000_1000));
+        assert_eq!(lookup(&root, 0x20_0000), Some(0x8000_2000));
+        assert_eq!(lookup(&root, 0x3000), None);
+        assert_eq!(lookup(&root, 0x4000_0000), None);
+    }
+
+    #[test]
+    fn remap() {
+        let mut root = Box::new(PageTable::new());
+        let mut tables = Vec::new();
+        map(&mut root, &mut tables, 0x1000, 0x8000_0000).unwrap();
+        assert_eq!(
+            map(&mut root, &mut tables, 0x1000, 0x8000_1000),
+            Err("remap")
+        );
+        assert_eq!(lookup(&root, 0x1000), Some(0x8000_0000));
+    }
+
+    #[test]
+    fn superpage() {
+        let mut root = Box::new(PageTable::new());
+        let mut tables = Vec::new();
+        let mega = PageTableLevel::One.page_size();
+        let giga = PageTableLevel::Two.page_size();
+
+        // a 2 MiB page needs no level 0 table
+        map_level(
+            &mut root,
+            &mut tables,
+            mega,
+            0x8020_0000,
+            PageTableLevel::One,
+        )
+        .unwrap();
+        assert_eq!(tables.len(), LOWER_LEVELS - 1);
+        assert_eq!(lookup(&root, mega), Some(0x8020_0000));
+        assert_eq!(lookup(&root, mega + 0x1234), Some(0x8020_1000));
+        assert_eq!(lookup(&root, 2 * mega - 1), Some(0x8040_0000 - 0x1000));
+        assert_eq!(lookup(&root, 2 * mega), None);
+        // the walk stops at the superpage
+        assert_eq!(
+            map(&mut root, &mut tables, mega + 0x1000, 0x9000_0000),
+            Err("remap")
+        );
+
+        // a 1 GiB page
+        map_level(
+            &mut root,
+            &mut tables,
+            giga,
+            0x4000_0000,
+            PageTableLevel::Two,
+        )
+        .unwrap();
+        assert_eq!(lookup(&root, giga + 3 * mega + 0x5000), Some(0x4060_5000));
+        assert_eq!(
+            map_level(&mut root, &mut tables, giga + mega, 0, PageTableLevel::One),
+            Err("remap")
+        );
+    }
+
+    #[test]
+    fn invalid_va() {
+        let mut root = Box::new(PageTable::new());
+        let mut tables = Vec::new();
+        assert_eq!(
+            map(&mut root, &mut tables, MAXVA, 0x8000_0000),
+            Err("invalid va")
+        );
+        assert_eq!(lookup(&root, MAXVA), None);
+        assert!(tables.is_empty());
+    }
+
+    proptest! {
+        #[test]
+        fn lookup_finds_mapped(pages in prop::collection::btree_set(0..MAXVA >> 12, 1..64)) {
+            let mut root = Box::new(PageTable::new());
+            let mut tables = Vec::new();
+            for (i, page) in pages.iter().enumerate() {
+                map(&mut root, &mut tables, page << 12, (i as u64 + 1) << 12).unwrap();
+            }
+            for (i, page) in pages.iter().enumerate() {
+                prop_assert_eq!(lookup(&root, (page << 12) | 0xfff), Some((i as u64 + 1) << 12));
+                if !pages.contains(&(page + 1)) && page + 1 < MAXVA >> 12 {
+                    prop_assert_eq!(lookup(&root, (page + 1) << 12), None);
+                }
+            }
+        }
+    }
+}
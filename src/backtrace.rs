@@ -0,0 +1,80 @@
+//! Kernel backtrace by walking the frame pointer chain.
+//!
+//! The kernel is built with force-frame-pointers, so every frame keeps
+//! the return address at fp-8 and the frame pointer of its caller at fp-16.
+//! The panic handler names the addresses with the symbol table in ksyms.rs.
+
+use crate::cpu::try_get_cpu;
+use crate::memorylayout::KSTACK_SIZE;
+use crate::param::{NCPU, OS_STACK_SIZE};
+
+use core::arch::asm;
+
+// deep enough for the kernel, bounds the walk of a corrupted chain
+const MAX_DEPTH: usize = 64;
+
+extern "C" {
+    // the boot stacks in start.rs and the trap stacks in trap.rs
+    static STACK0: u8;
+    static TRAP_STACK: u8;
+}
+
+#[inline(always)]
+fn read_fp() -> u64 {
+    let fp: u64;
+    unsafe { asm!("mv {}, s0", out(reg) fp) };
+    fp
+}
+
+/// The kernel stack containing addr, as (bottom, top)
+fn stack_bounds(addr: u64) -> Option<(u64, u64)> {
+    let boot = unsafe { &STACK0 as *const u8 as u64 };
+    let trap = unsafe { &TRAP_STACK as *const u8 as u64 };
+    let size = OS_STACK_SIZE as u64;
+    let mut stacks = [
+        (boot, boot + size * NCPU as u64),
+        (trap, trap + size * NCPU as u64),
+    ];
+    // the per hart stacks are adjacent, narrow down to the one of addr
+    for (bottom, top) in stacks.iter_mut() {
+        if *bottom <= addr && addr < *top {
+            *bottom += (addr - *bottom) / size * size;
+            *top = *bottom + size;
+        }
+    }
+    let proc_stack = try_get_cpu()
+        .map(|cpu| cpu.proc)
+        .filter(|proc| !proc.is_null())
+        .map(|proc| {
+            let kstack = unsafe { (*proc).kstack };
+            (kstack, kstack + KSTACK_SIZE)
+        });
+    stacks
+        .iter()
+        .copied()
+        .chain(proc_stack)
+        .find(|(bottom, top)| *bottom <= addr && addr < *top)
+}
+
+/// Call f with the return address of each caller, innermost first.
+/// The walk stops at the top of the stack it starts on.
+#[inline(never)]
+pub fn backtrace(mut f: impl FnMut(u64)) {
+    let mut fp = read_fp();
+    let (bottom, top) = match stack_bounds(fp) {
+        Some(bounds) => bounds,
+        None => return,
+    };
+    for _ in 0..MAX_DEPTH {
+        if fp % 8 != 0 || fp < bottom + 16 || fp > top {
+            break;
+        }
+        let (ra, prev) = unsafe { (*((fp - 8) as *const u64), *((fp - 16) as *const u64)) };
+        f(ra);
+        // the caller's frame is above on the stack
+        if prev <= fp {
+            break;
+        }
+        fp = prev;
+    }
+}
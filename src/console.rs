@@ -8,16 +8,18 @@
 //! * control-u -- kill line
 //! * control-d -- end of file
 //! * control-p -- print process list
+//! * control-l -- print lock statistics
 
 const BACKSPACE: u32 = 0x100;
 
+use crate::lockstat;
 use crate::param::CONSOLE_BUF_SIZE;
+use crate::spinlock::SpinLock;
 use crate::uart::Uart;
 use lazy_static::lazy_static;
-use spin::Mutex;
 
 lazy_static! {
-    pub static ref CONSOLE: Mutex<Console> = Mutex::new(Console::new());
+    pub static ref CONSOLE: SpinLock<Console> = SpinLock::new("console", Console::new());
 }
 
 pub struct Console {
@@ -46,6 +48,11 @@ impl Console {
     pub fn console_interrupt(&mut self, c: char, uart: &mut Uart) {
         match c {
             '\0' => {} // Do nothing if it is a null character
+            '\x0c' => {
+                // control-l, the UART is held so print through it
+                uart.puts(&lockstat::report());
+                uart.putc('\n');
+            }
             _ => {
                 // echo character to user
                 self.console_putc(c, uart);
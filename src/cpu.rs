@@ -3,26 +3,40 @@
 use crate::param::NCPU;
 use crate::proc::Proc;
 use crate::proc_util::Context;
-use crate::trap::{pop_off, push_off};
+use crate::scheduler::RunQueue;
+use crate::spinlock::SpinLock;
+use crate::trap::{pop_off, push_off, TrapInfo};
 use alloc::boxed::Box;
 use core::ptr;
+use core::sync::atomic::AtomicBool;
 use rv64::register::tp;
-use spin::Mutex;
 
 pub struct Cpu {
+    pub id: u64,              // hart id
     pub proc: *mut Box<Proc>, // the process id running on this cpu
     pub context: Context,
-    pub interrupt_base: Mutex<bool>,
-    pub push_count: Mutex<u32>,
+    pub interrupt_base: bool, // were interrupts enabled before the first push_off
+    pub push_count: u32,      // depth of push_off nesting
+    pub run_queue: SpinLock<RunQueue>, // processes waiting to run on this cpu
+    pub online: AtomicBool,   // the hart is running the scheduler
+    #[cfg(feature = "tickless")]
+    pub idle: AtomicBool, // the hart waits in wfi with its tick stopped
+    pub trap: Option<TrapInfo>, // the kernel trap this hart panics on
 }
 
 impl Cpu {
-    pub const fn new() -> Self {
+    pub const fn new(id: u64) -> Self {
         Self {
+            id,
             proc: ptr::null_mut(),
             context: Context::new(),
-            interrupt_base: Mutex::new(false),
-            push_count: Mutex::new(0),
+            interrupt_base: false,
+            push_count: 0,
+            run_queue: SpinLock::new("run_queue", RunQueue::new()),
+            online: AtomicBool::new(false),
+            #[cfg(feature = "tickless")]
+            idle: AtomicBool::new(false),
+            trap: None,
         }
     }
 }
@@ -35,7 +49,7 @@ static mut CPU: [Option<Cpu>; NCPU] = {
 pub fn init_cpu() {
     for i in 0..NCPU {
         unsafe {
-            CPU[i] = Some(Cpu::new());
+            CPU[i] = Some(Cpu::new(i as u64));
         }
     }
 }
@@ -52,6 +66,17 @@ pub fn get_cpu() -> &'static mut Cpu {
     unsafe { CPU[id].as_mut().unwrap() }
 }
 
+/// get_cpu that also works before init_cpu, for the panic handler
+pub fn try_get_cpu() -> Option<&'static mut Cpu> {
+    let id = get_cpuid() as usize;
+    unsafe { CPU.get_mut(id).and_then(|cpu| cpu.as_mut()) }
+}
+
+/// Iterate over the Cpu of all harts
+pub fn get_cpus() -> impl Iterator<Item = &'static mut Cpu> {
+    unsafe { CPU.iter_mut().filter_map(|cpu| cpu.as_mut()) }
+}
+
 pub fn get_proc() -> *mut Box<Proc> {
     push_off();
     let cpu = get_cpu();
@@ -1,45 +1,206 @@
+//! virtio block device driver
+//!
+//! One request is in flight at a time: the process issuing it holds the
+//! DISK SleepLock and sleeps until the device interrupt, or the timeout
+//! timer, wakes it up, so other processes waiting for the disk sleep too.
+//! There is no file system yet, the tests are the only callers.
+
 use crate::memorylayout::VIRTIO0;
+use crate::param::{DISK_TIMEOUT_TICKS, TIMER_INTERVAL};
+use crate::println;
+use crate::scheduler::{get_scheduler, sleep};
+use crate::sleeplock::SleepLock;
+use crate::spinlock::SpinLock;
+use crate::timer::{add_timer, cancel_timer, mtime};
 use crate::virtio::block::{BlockRequest, RequestType, VirtioBlock};
 use crate::virtio::header::VirtioHeader;
 use crate::virtio::queue::DescriptorFlag;
 
 use core::mem::size_of;
-use rv64::asm::sync_synchronize;
-use spin::Mutex;
+use core::ptr::read_volatile;
+
+/// Size of a disk sector in bytes
+pub const SECTOR_SIZE: usize = 512;
+
+// status written by the device
+const STATUS_OK: u8 = 0;
+
+struct Disk {
+    block: VirtioBlock,
+    // the request in flight, kept here instead of on the kernel stack
+    // as the device reads and writes it at its physical address
+    request: BlockRequest,
+    data: [u8; SECTOR_SIZE],
+    status: u8,
+    /// Set when a request timed out. The device is not reset and may
+    /// still complete it, so its descriptors are never handed out again.
+    failed: bool,
+}
 
-static mut DISK: Mutex<Option<VirtioBlock>> = Mutex::new(None);
+static mut DISK: SleepLock<Option<Disk>> = SleepLock::new("disk", None);
 
+/// Set by the timeout timer, the request path sleeps on it
+static TIMED_OUT: SpinLock<bool> = SpinLock::new("disk_intr", false);
+
+/// Initialize the virtio disk, the kernel runs without it if qemu has none
 pub fn init_disk() {
-    let header = VirtioHeader::new(VIRTIO0).expect("Error: Disk header initialization");
-    let block = VirtioBlock::new(header).expect("Error: Disk initialization");
+    let block = match VirtioHeader::new(VIRTIO0).and_then(VirtioBlock::new) {
+        Ok(block) => block,
+        Err(e) => {
+            println!("disk: {:?}, running without disk", e);
+            return;
+        }
+    };
+    let disk = Disk {
+        block,
+        request: BlockRequest {
+            typ: RequestType::In,
+            reserved: 0,
+            sector: 0,
+        },
+        data: [0; SECTOR_SIZE],
+        status: 0,
+        failed: false,
+    };
+    // there is no process to take the lock yet
     unsafe {
-        let mut disk = DISK.lock();
-        *disk = Some(block);
+        *DISK.get_mut() = Some(disk);
     }
 }
 
-pub fn read_disk() {
+/// Read sector into buf, sleeping until the disk completes the request
+pub fn read_disk(sector: u64, buf: &mut [u8; SECTOR_SIZE]) -> Result<(), &'static str> {
     let mut disk = unsafe { DISK.lock() };
+    let disk = disk.as_mut().ok_or("disk: no disk")?;
+    request(disk, RequestType::In, sector)?;
+    buf.copy_from_slice(&disk.data);
+    Ok(())
+}
 
-    let request = BlockRequest {
-        typ: RequestType::In,
+/// Write buf to sector, sleeping until the disk completes the request
+pub fn write_disk(sector: u64, buf: &[u8; SECTOR_SIZE]) -> Result<(), &'static str> {
+    let mut disk = unsafe { DISK.lock() };
+    let disk = disk.as_mut().ok_or("disk: no disk")?;
+    disk.data.copy_from_slice(buf);
+    request(disk, RequestType::Out, sector)
+}
+
+/// Submit a request for disk.data and wait for its completion
+fn request(disk: &mut Disk, typ: RequestType, sector: u64) -> Result<(), &'static str> {
+    // the request buffers in Disk are used by one process at a time
+    assert!(unsafe { DISK.holding() });
+    if disk.failed {
+        return Err("disk: failed after a timeout");
+    }
+
+    let data_flag = match typ {
+        RequestType::In => DescriptorFlag::NEXT | DescriptorFlag::WRITE,
+        RequestType::Out => DescriptorFlag::NEXT,
+    };
+    disk.request = BlockRequest {
+        typ,
         reserved: 0,
-        sector: 0,
+        sector,
     };
+    disk.status = 0xff;
+
+    // request header, data and status in a chain of three descriptors
+    let chain = [
+        (
+            &disk.request as *const _ as u64,
+            size_of::<BlockRequest>() as u32,
+            DescriptorFlag::NEXT,
+        ),
+        (disk.data.as_ptr() as u64, SECTOR_SIZE as u32, data_flag),
+        (&disk.status as *const _ as u64, 1, DescriptorFlag::WRITE),
+    ];
+    let queue = &mut disk.block.queue;
+    for (i, &(addr, len, flags)) in chain.iter().enumerate() {
+        let descriptor = queue.desc_mut(i as u16);
+        descriptor.addr = addr;
+        descriptor.len = len;
+        descriptor.flags = flags;
+        descriptor.next = i as u16 + 1;
+    }
+    let done = queue.used_idx().wrapping_add(1);
 
-    let block = disk.as_mut().unwrap();
-    let mut descriptor = unsafe { block.queue.desc.as_mut() };
-    let mut available = unsafe { block.queue.avail.as_mut() };
+    *TIMED_OUT.lock() = false;
+    let timer = add_timer(mtime() + DISK_TIMEOUT_TICKS * TIMER_INTERVAL, || {
+        *TIMED_OUT.lock() = true;
+        get_scheduler().wakeup(disk_chan());
+    });
+    queue.push_avail(0);
+    disk.block.header.set_queue_notify(0);
 
-    // setup block request
-    descriptor.addr = &request as *const _ as u64;
-    descriptor.len = size_of::<BlockRequest>() as u32;
-    descriptor.flags = DescriptorFlag::NEXT;
-    descriptor.next = 0;
+    let mut timed_out = TIMED_OUT.lock();
+    while disk.block.queue.used_idx() != done && !*timed_out {
+        timed_out = sleep(disk_chan(), &TIMED_OUT, timed_out);
+    }
+    drop(timed_out);
+    cancel_timer(timer);
+
+    if disk.block.queue.used_idx() != done {
+        disk.failed = true;
+        return Err("disk: request timed out");
+    }
+    match unsafe { read_volatile(&disk.status) } {
+        STATUS_OK => Ok(()),
+        _ => Err("disk: request failed"),
+    }
+}
 
-    available.idx += 1;
+/// sleep channel of the process waiting for its request
+fn disk_chan() -> usize {
+    &TIMED_OUT as *const SpinLock<bool> as usize
+}
+
+/// Handle the disk interrupt, wake up the process waiting for its request
+pub fn disk_intr() {
+    if let Ok(header) = VirtioHeader::new(VIRTIO0) {
+        header.ack_interrupt();
+    }
+    get_scheduler().wakeup(disk_chan());
+}
 
-    sync_synchronize();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proc::spawn_kthread;
+    use crate::testing::wait_until;
 
-    block.header.set_queue_notify(0);
+    use core::sync::atomic::{AtomicBool, Ordering};
+
+    // the test runner attaches a scratch disk image
+    const SECTOR: u64 = 1;
+
+    #[test_case]
+    fn write_read_sector() {
+        let mut buf = [0; SECTOR_SIZE];
+        for (i, byte) in buf.iter_mut().enumerate() {
+            *byte = i as u8 ^ 0x5a;
+        }
+        write_disk(SECTOR, &buf).unwrap();
+        let mut read = [0; SECTOR_SIZE];
+        read_disk(SECTOR, &mut read).unwrap();
+        assert!(read == buf);
+    }
+
+    static READ: AtomicBool = AtomicBool::new(false);
+
+    fn reader() {
+        let mut buf = [0; SECTOR_SIZE];
+        read_disk(SECTOR, &mut buf).unwrap();
+        READ.store(true, Ordering::Release);
+    }
+
+    #[test_case]
+    fn reader_sleeps_while_disk_held() {
+        let disk = unsafe { DISK.lock() };
+        let pid = spawn_kthread("disk_test", reader).unwrap();
+        let chan = unsafe { DISK.chan() };
+        assert!(wait_until(100, || get_scheduler().sleeping_on(pid, chan)));
+        assert!(!READ.load(Ordering::Acquire));
+        drop(disk);
+        assert!(wait_until(100, || READ.load(Ordering::Acquire)));
+    }
 }
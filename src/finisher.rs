@@ -0,0 +1,32 @@
+//! SiFive test finisher, as provided by qemu -machine virt.
+//! Writing to it stops or resets the machine.
+
+use crate::memorylayout::FINISHER;
+
+// values written to the finisher register
+const FINISHER_FAIL: u32 = 0x3333;
+const FINISHER_PASS: u32 = 0x5555;
+const FINISHER_RESET: u32 = 0x7777;
+
+fn write_finisher(val: u32) -> ! {
+    unsafe {
+        core::ptr::write_volatile(FINISHER as *mut u32, val);
+    }
+    // the write does not return on qemu
+    loop {}
+}
+
+/// Power off qemu, exit with status code.
+/// A status other than 0 marks a failure.
+pub fn shutdown(code: u16) -> ! {
+    if code == 0 {
+        write_finisher(FINISHER_PASS)
+    } else {
+        write_finisher(FINISHER_FAIL | (code as u32) << 16)
+    }
+}
+
+/// Reset the machine and boot again
+pub fn reboot() -> ! {
+    write_finisher(FINISHER_RESET)
+}
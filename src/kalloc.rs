@@ -1,10 +1,38 @@
 use crate::memorylayout;
 use crate::riscv::PAGESIZE;
+use crate::spinlock::SpinLock;
 use crate::ALLOCATOR;
 use alloc::alloc::Layout;
-use alloc::alloc::{alloc, dealloc};
+use alloc::alloc::{alloc, dealloc, GlobalAlloc};
+use linked_list_allocator::Heap;
 
-use core::ptr::write_bytes;
+use core::ptr::{self, write_bytes, NonNull};
+
+/// The heap behind the global allocator, locked with a SpinLock
+/// so an interrupt handler can allocate without deadlocking
+/// against the code it interrupted.
+pub struct KernelHeap(SpinLock<Heap>);
+
+impl KernelHeap {
+    pub const fn empty() -> Self {
+        Self(SpinLock::new("heap", Heap::empty()))
+    }
+}
+
+unsafe impl GlobalAlloc for KernelHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.0
+            .lock()
+            .allocate_first_fit(layout)
+            .map_or(ptr::null_mut(), |ptr| ptr.as_ptr())
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.0
+            .lock()
+            .deallocate(NonNull::new_unchecked(ptr), layout)
+    }
+}
 
 pub fn init_heap() {
     extern "C" {
@@ -15,7 +43,7 @@ pub fn init_heap() {
     let heap_start: usize = unsafe { &_END as *const usize as usize };
     let heap_end = memorylayout::PHYSTOP as usize;
     let heap_size = heap_end - heap_start;
-    unsafe { ALLOCATOR.lock().init(heap_start, heap_size) }
+    unsafe { ALLOCATOR.0.lock().init(heap_start, heap_size) }
 }
 
 /// Allocate one 4096-byte page of physical memory.
@@ -36,3 +64,44 @@ pub fn kfree(ptr: *mut u8) {
         dealloc(ptr, layout);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    #[test_case]
+    fn kalloc_page() {
+        let ptr = kalloc();
+        assert!(!ptr.is_null());
+        assert_eq!(ptr as u64 % PAGESIZE, 0);
+        let page = unsafe { core::slice::from_raw_parts(ptr, PAGESIZE as usize) };
+        assert!(page.iter().all(|b| *b == 0));
+        kfree(ptr);
+    }
+
+    #[test_case]
+    fn kalloc_distinct() {
+        let mut pages: Vec<*mut u8> = (0..16).map(|_| kalloc()).collect();
+        pages.sort();
+        for pair in pages.windows(2) {
+            // pages do not overlap
+            assert!(pair[1] as u64 - pair[0] as u64 >= PAGESIZE);
+        }
+        for ptr in pages {
+            assert!((ptr as u64) < memorylayout::PHYSTOP);
+            kfree(ptr);
+        }
+    }
+
+    #[test_case]
+    fn kalloc_zeroed_after_free() {
+        let ptr = kalloc();
+        unsafe { write_bytes(ptr, 0xff, PAGESIZE as usize) };
+        kfree(ptr);
+        let ptr = kalloc();
+        let page = unsafe { core::slice::from_raw_parts(ptr, PAGESIZE as usize) };
+        assert!(page.iter().all(|b| *b == 0));
+        kfree(ptr);
+    }
+}
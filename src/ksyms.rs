@@ -0,0 +1,71 @@
+//! Kernel symbol table
+//!
+//! The .ksyms section is reserved zeroed at link time and filled by
+//! tools/ksyms.py with the function symbols sorted by address,
+//! so backtraces print function+offset without a debugger.
+
+use core::mem::size_of;
+use core::ptr::addr_of;
+use core::str::from_utf8;
+
+// room for the symbol table, tools/ksyms.py fails if the table does not fit
+const KSYMS_SIZE: usize = 256 * 1024;
+
+const MAGIC: &[u8; 4] = b"KSYM";
+const HEADER_SIZE: usize = 8;
+const ENTRY_SIZE: usize = 16;
+
+#[link_section = ".ksyms"]
+#[used]
+static mut KSYMS: [u8; KSYMS_SIZE] = [0; KSYMS_SIZE];
+
+fn read_u32(table: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0; size_of::<u32>()];
+    bytes.copy_from_slice(&table[offset..offset + size_of::<u32>()]);
+    u32::from_le_bytes(bytes)
+}
+
+fn read_u64(table: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0; size_of::<u64>()];
+    bytes.copy_from_slice(&table[offset..offset + size_of::<u64>()]);
+    u64::from_le_bytes(bytes)
+}
+
+/// The symbol table, None if ksyms.py has not filled it
+fn table() -> Option<(&'static [u8], usize)> {
+    let table: &'static [u8] = unsafe { &*addr_of!(KSYMS) };
+    if &table[..MAGIC.len()] != MAGIC {
+        return None;
+    }
+    let count = read_u32(table, MAGIC.len()) as usize;
+    (HEADER_SIZE + count * ENTRY_SIZE <= KSYMS_SIZE).then(|| (table, count))
+}
+
+/// Find the function containing addr, return its name and the offset of addr in it
+pub fn lookup(addr: u64) -> Option<(&'static str, u64)> {
+    let (table, count) = table()?;
+    let entry = |i: usize| HEADER_SIZE + i * ENTRY_SIZE;
+
+    // the last symbol at or below addr
+    let (mut low, mut high) = (0, count);
+    while low < high {
+        let mid = (low + high) / 2;
+        if read_u64(table, entry(mid)) <= addr {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    let i = low.checked_sub(1)?;
+
+    let start = read_u64(table, entry(i));
+    let size = read_u32(table, entry(i) + 8) as u64;
+    let offset = addr - start;
+    if size != 0 && offset >= size {
+        return None;
+    }
+    let name_start = read_u32(table, entry(i) + 12) as usize;
+    let name_len = table.get(name_start..)?.iter().position(|c| *c == 0)?;
+    let name = from_utf8(&table[name_start..name_start + name_len]).ok()?;
+    Some((name, offset))
+}
@@ -1,26 +1,36 @@
 use rv64::asm::sfence_vma;
-use rv64::csr::satp::{Satp, SatpMode};
+use rv64::csr::satp::Satp;
 
 use crate::kalloc::{kalloc, kfree};
 use crate::memorylayout::{
-    kstack, KERNELBASE, PHYSTOP, PLIC_BASE, TRAMPOLINE, TRAPFRAME, UART0, VIRTIO0,
+    CLINT, FINISHER, KERNELBASE, KSTACK_SIZE, PHYSTOP, PLIC_BASE, RTC0, TRAMPOLINE, TRAPFRAME,
+    UART0, VIRTIO0,
 };
-use crate::param::NPROC;
+use crate::println;
 use crate::proc::Proc;
-use crate::riscv::{MAXVA, PAGESIZE};
+use crate::riscv::{MAXVA, PAGESIZE, SATP_MODE};
+use crate::spinlock::SpinLock;
 use crate::vm::addr::{align_down, align_up, PhysAddr, VirtAddr};
 use crate::vm::page_flag::PteFlag;
-use crate::vm::page_table::{PageTable, PageTableEntry, PageTableLevel};
+use crate::vm::page_table::{PageTable, PageTableEntry, PageTableLevel, ROOT_LEVEL};
 use crate::vm::page_table_walker::{
     PageTableVisitor, PageTableVisitorMut, PageTableWalker, PageTableWalkerMut,
 };
 
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
 use core::cmp;
 use core::ptr::{copy, write_bytes, NonNull};
 use core::slice::from_raw_parts;
 
 static mut KERNELPAGE: Option<&mut PageTable> = None;
 
+/// Serialize changes to the kernel page table after boot,
+/// kernel stacks are mapped while other harts are running.
+static KERNELPAGE_LOCK: SpinLock<()> = SpinLock::new("kernelpage", ());
+
 pub fn init_kvm() {
     extern "C" {
         static _trampoline: usize;
@@ -48,6 +58,30 @@ pub fn init_kvm() {
         PteFlag::PTE_READ | PteFlag::PTE_WRITE,
     );
 
+    // map test finisher register
+    kvmmap(
+        VirtAddr::new(FINISHER),
+        PhysAddr::new(FINISHER),
+        PAGESIZE,
+        PteFlag::PTE_READ | PteFlag::PTE_WRITE,
+    );
+
+    // map goldfish RTC registers
+    kvmmap(
+        VirtAddr::new(RTC0),
+        PhysAddr::new(RTC0),
+        PAGESIZE,
+        PteFlag::PTE_READ | PteFlag::PTE_WRITE,
+    );
+
+    // map CLINT registers, for mtime and mtimecmp
+    kvmmap(
+        VirtAddr::new(CLINT),
+        PhysAddr::new(CLINT),
+        0x10000,
+        PteFlag::PTE_READ | PteFlag::PTE_WRITE,
+    );
+
     // map PLIC registers
     kvmmap(
         VirtAddr::new(PLIC_BASE),
@@ -80,23 +114,12 @@ pub fn init_kvm() {
         PAGESIZE,
         PteFlag::PTE_READ | PteFlag::PTE_EXEC,
     );
-
-    // alloc and map stack for kernel process
-    for i in 0u64..NPROC as u64 {
-        let ptr = kalloc();
-        if ptr == 0 as *mut u8 {
-            panic!("kalloc failed in alloc proc stack");
-        }
-        let pa = PhysAddr::new(ptr as *const _ as u64);
-        let va = VirtAddr::new(kstack(i));
-        kvmmap(va, pa, PAGESIZE, PteFlag::PTE_READ | PteFlag::PTE_WRITE);
-    }
 }
 
 pub fn init_page() {
     let mut satp = Satp::from_bits(0);
     let ptr = unsafe { get_root_page() };
-    satp.set_mode(SatpMode::ModeSv39);
+    satp.set_mode(SATP_MODE);
     satp.set_addr(ptr as *const _ as u64);
     satp.write();
     sfence_vma();
@@ -113,9 +136,45 @@ fn kvmmap(va: VirtAddr, pa: PhysAddr, size: u64, perm: PteFlag) {
     map_pages(page_table, va, pa, size, perm).expect("map_pages_error");
 }
 
+/// Allocate the pages of a kernel stack and map them starting at va.
+/// The page below va is left unmapped as the guard page.
+pub fn map_kstack(va: VirtAddr) -> Result<(), &'static str> {
+    let _lock = KERNELPAGE_LOCK.lock();
+    let page_table = unsafe { get_root_page() };
+    let npages = KSTACK_SIZE / PAGESIZE;
+
+    for i in 0..npages {
+        let ptr = kalloc();
+        let result = if ptr.is_null() {
+            Err("kalloc failed in alloc proc stack")
+        } else {
+            let pa = PhysAddr::new(ptr as *const _ as u64);
+            map_pages(
+                page_table,
+                va + i * PAGESIZE,
+                pa,
+                PAGESIZE,
+                PteFlag::PTE_READ | PteFlag::PTE_WRITE,
+            )
+            .map_err(|e| {
+                kfree(ptr);
+                e
+            })
+        };
+        if let Err(e) = result {
+            unmap_pages(page_table, va, i, true).expect("map_kstack");
+            return Err(e);
+        }
+    }
+    sfence_vma();
+    Ok(())
+}
+
 struct PageMapper {
     pa: PhysAddr,
     perm: PteFlag,
+    /// level of the leaf entry, above level 0 for a superpage
+    level: PageTableLevel,
 }
 
 impl PageTableVisitorMut for PageMapper {
@@ -124,8 +183,8 @@ impl PageTableVisitorMut for PageMapper {
         va < VirtAddr::new(MAXVA)
     }
 
-    fn leaf(&mut self, pte: &mut PageTableEntry) -> Self::Output {
-        if pte.is_unused() {
+    fn leaf(&mut self, pte: &mut PageTableEntry, level: PageTableLevel) -> Self::Output {
+        if pte.is_unused() && level == self.level {
             pte.set_addr(self.pa.as_pte(), self.perm | PteFlag::PTE_VALID);
             Ok(())
         } else {
@@ -144,28 +203,44 @@ impl PageTableVisitorMut for PageMapper {
         }
         Ok(())
     }
+
+    fn leaf_level(&self) -> PageTableLevel {
+        self.level
+    }
 }
 
 struct PageUnmapper {
     do_free: bool,
+    /// address being unmapped, a superpage must start at it
+    va: u64,
+    /// bytes left to unmap, a superpage must fit in them
+    size: u64,
 }
 
 impl PageTableVisitorMut for PageUnmapper {
-    type Output = Result<(), &'static str>;
+    /// the bytes unmapped, more than a page for a superpage
+    type Output = Result<u64, &'static str>;
     fn is_valid_va(&self, va: VirtAddr) -> bool {
         va < VirtAddr::new(MAXVA)
     }
 
-    fn leaf(&mut self, pte: &mut PageTableEntry) -> Self::Output {
+    fn leaf(&mut self, pte: &mut PageTableEntry, level: PageTableLevel) -> Self::Output {
+        let page_size = level.page_size();
         if pte.is_unused() {
             Err("unmap_page: not mapped")
-        } else if pte.flag() == PteFlag::PTE_VALID {
+        } else if !pte.is_leaf() {
             Err("unmap_page: not leaf")
+        } else if self.va % page_size != 0 || page_size > self.size {
+            Err("unmap_page: part of superpage")
         } else {
-            let addr = pte.addr();
-            kfree(addr as *mut _);
+            if self.do_free {
+                // superpages are freed page by page, like they were allocated
+                for offset in (0..page_size).step_by(PAGESIZE as usize) {
+                    kfree((pte.addr() + offset) as *mut _);
+                }
+            }
             pte.set_unused();
-            Ok(())
+            Ok(page_size)
         }
     }
 
@@ -173,12 +248,29 @@ impl PageTableVisitorMut for PageUnmapper {
         if pte.is_unused() {
             return Err("unmap_page: walk");
         }
-        Ok(())
+        Ok(0)
+    }
+}
+
+/// The highest level, up to 1 GiB pages, whose page can map va to pa
+/// with both aligned to the page size and size bytes left to map.
+fn superpage_level(va: u64, pa: u64, size: u64) -> PageTableLevel {
+    let mut level = PageTableLevel::Zero;
+    for next in [PageTableLevel::One, PageTableLevel::Two] {
+        let page_size = next.page_size();
+        if va % page_size != 0 || pa % page_size != 0 || size < page_size {
+            break;
+        }
+        level = next;
     }
+    level
 }
+
 /// Create PTEs for virtual addresses starting at va that refer to
 /// physical addresses starting at pa. va and size might not
 /// be page-aligned.
+/// Where va and pa are aligned to 2 MiB or 1 GiB and enough of size
+/// is left, one superpage entry maps the whole superpage.
 /// Return Errs if it cannot allocate the needed page-table.
 fn map_pages(
     page_table: &mut PageTable,
@@ -187,22 +279,19 @@ fn map_pages(
     size: u64,
     perm: PteFlag,
 ) -> Result<(), &'static str> {
-    let va_start = va.align_down();
-    let va_end = VirtAddr::new_truncate(va.as_u64() + size - 1).align_down();
-    let mut page_addr = va_start;
+    let mut page_addr = va.align_down().as_u64();
+    let va_end = align_up(va.as_u64() + size, PAGESIZE);
 
-    loop {
-        let mapper = PageMapper { pa, perm };
+    while page_addr < va_end {
+        let level = superpage_level(page_addr, pa.as_u64(), va_end - page_addr);
+        let mapper = PageMapper { pa, perm, level };
         let mut walker =
-            PageTableWalkerMut::new(page_table, page_addr, PageTableLevel::Two, mapper)
+            PageTableWalkerMut::new(page_table, VirtAddr::new(page_addr), ROOT_LEVEL, mapper)
                 .ok_or("map_page: virtual address over MAX address")?;
         walker.visit_mut()?;
 
-        if page_addr == va_end {
-            break;
-        }
-        page_addr += PAGESIZE;
-        pa += PAGESIZE;
+        page_addr += level.page_size();
+        pa += level.page_size();
     }
 
     Ok(())
@@ -264,9 +353,40 @@ pub fn init_uvm(page_table: &mut PageTable, code: &[u8]) {
     }
 }
 
+/// Allocate a zeroed page and map it at va in the user page table
+pub fn map_user_page(
+    page_table: &mut PageTable,
+    va: VirtAddr,
+    perm: PteFlag,
+) -> Result<(), &'static str> {
+    let ptr = kalloc();
+    if ptr.is_null() {
+        return Err("map_user_page: kalloc");
+    }
+    let pa = PhysAddr::new(ptr as u64);
+    map_pages(page_table, va, pa, PAGESIZE, perm | PteFlag::PTE_USER).map_err(|e| {
+        kfree(ptr);
+        e
+    })
+}
+
+/// Unmap and free the user pages mapped in npages starting from va,
+/// skipping the pages never populated.
+pub fn unmap_user_pages(page_table: &mut PageTable, va: VirtAddr, npages: u64) {
+    for i in 0..npages {
+        let addr = va + i * PAGESIZE;
+        if map_addr(page_table, addr, PteFlag::empty()).is_some() {
+            unmap_pages(page_table, addr, 1, true).expect("unmap_user_pages");
+        }
+    }
+}
+
 pub fn clear_user_pagetable(proc: &mut Proc) {
     unsafe {
         let page_table = proc.pagetable.as_mut();
+        for vma in proc.vmas.drain(..) {
+            unmap_user_pages(page_table, VirtAddr::new(vma.start), vma.npages());
+        }
         unmap_pages(page_table, VirtAddr::new(TRAMPOLINE), 1, false)
             .and(unmap_pages(page_table, VirtAddr::new(TRAPFRAME), 1, false))
             .and(unmap_free(page_table, proc.memory_size))
@@ -275,6 +395,7 @@ pub fn clear_user_pagetable(proc: &mut Proc) {
 }
 
 /// Remove npages of mappings starting fom va. va must be page-aligned.
+/// A superpage is removed whole, it must lie inside the npages.
 /// panic! if mappings is not exist.
 /// Optional: free the physical memory.
 fn unmap_pages(
@@ -288,13 +409,17 @@ fn unmap_pages(
     }
 
     let mut addr = va;
-    while addr < va + npages * PAGESIZE {
-        let unmapper = PageUnmapper { do_free };
-        let mut walker = PageTableWalkerMut::new(page_table, addr, PageTableLevel::Two, unmapper)
+    let end = va + npages * PAGESIZE;
+    while addr < end {
+        let size = end.as_u64() - addr.as_u64();
+        let unmapper = PageUnmapper {
+            do_free,
+            va: addr.as_u64(),
+            size,
+        };
+        let mut walker = PageTableWalkerMut::new(page_table, addr, ROOT_LEVEL, unmapper)
             .ok_or("unmap_page: virtual address over MAX address")?;
-        walker.visit_mut()?;
-
-        addr += PAGESIZE;
+        addr += walker.visit_mut()?;
     }
 
     Ok(())
@@ -303,15 +428,17 @@ fn unmap_pages(
 fn free_pagetable(page_table: &mut PageTable, level: PageTableLevel) -> Result<(), &'static str> {
     for i in 0..512 {
         let pte = &mut page_table[i];
-        let flag = pte.flag();
-        if flag.contains(PteFlag::PTE_VALID) {
-            if !flag.intersects(PteFlag::PTE_READ | PteFlag::PTE_WRITE | PteFlag::PTE_EXEC) {
-                let next_table = unsafe { &mut *(pte.addr() as *mut PageTable) };
-                free_pagetable(next_table, level.next_level().unwrap())?;
-                pte.set_unused();
-            } else {
-                return Err("free_pagetable: leaf");
-            }
+        if pte.is_leaf() {
+            // a page or superpage is still mapped
+            return Err("free_pagetable: leaf");
+        }
+        if pte.flag().contains(PteFlag::PTE_VALID) {
+            let next_level = level
+                .next_level()
+                .ok_or("free_pagetable: table at level 0")?;
+            let next_table = unsafe { &mut *(pte.addr() as *mut PageTable) };
+            free_pagetable(next_table, next_level)?;
+            pte.set_unused();
         }
     }
     kfree(page_table as *mut PageTable as *mut _);
@@ -324,23 +451,29 @@ fn unmap_free(page_table: &mut PageTable, size: u64) -> Result<(), &'static str>
         let npages = align_up(size, PAGESIZE) / PAGESIZE;
         unmap_pages(page_table, va, npages, true)?;
     }
-    free_pagetable(page_table, PageTableLevel::Two)?;
+    free_pagetable(page_table, ROOT_LEVEL)?;
     Ok(())
 }
 
-struct AddrMapper;
+struct AddrMapper {
+    va: VirtAddr,
+    /// permission required on the leaf besides PTE_VALID and PTE_USER
+    perm: PteFlag,
+}
 
 impl PageTableVisitor for AddrMapper {
     type Output = Option<PhysAddr>;
     fn is_valid_va(&self, va: VirtAddr) -> bool {
         va < VirtAddr::new(MAXVA)
     }
-    fn leaf(&self, pte: &PageTableEntry) -> Self::Output {
+    fn leaf(&self, pte: &PageTableEntry, level: PageTableLevel) -> Self::Output {
         let flag = pte.flag();
-        if !flag.contains(PteFlag::PTE_VALID | PteFlag::PTE_USER) {
+        if !flag.contains(PteFlag::PTE_VALID | PteFlag::PTE_USER | self.perm) {
             return None;
         }
-        Some(PhysAddr::new(pte.addr()))
+        // the page of va inside a superpage
+        let offset = self.va.align_down().as_u64() & (level.page_size() - 1);
+        Some(PhysAddr::new(pte.addr() + offset))
     }
     fn nonleaf(&self, pte: &PageTableEntry) -> Self::Output {
         let flag = pte.flag();
@@ -353,29 +486,417 @@ impl PageTableVisitor for AddrMapper {
 
 /// Look up a virtual address, return Option physical address,
 /// Can only be used to look up user pages.
-fn map_addr(page_table: &PageTable, va: VirtAddr) -> Option<PhysAddr> {
-    let mapper = AddrMapper;
-    PageTableWalker::new(page_table, va, PageTableLevel::Two, mapper)
-        .and_then(|mut walker| walker.visit())
+fn map_addr(page_table: &PageTable, va: VirtAddr, perm: PteFlag) -> Option<PhysAddr> {
+    let mapper = AddrMapper { va, perm };
+    PageTableWalker::new(page_table, va, ROOT_LEVEL, mapper).and_then(|mut walker| walker.visit())
 }
 
+/// Copy the nul-terminated string at virtual address addr in the user
+/// page table to buf, the string may cross pages.
+/// Return its length, or buf.len() if buf fills up before the nul.
+/// Return None if a page is not mapped before the copy ends.
 pub fn copy_in_str(page_table: &mut PageTable, addr: u64, buf: &mut [u8]) -> Option<u64> {
-    let max_len = buf.len();
-    let base = align_down(addr, PAGESIZE);
-    let offset = addr - base;
-    let va = VirtAddr::new(base);
-    let pa = map_addr(page_table, va)?;
-    let n = cmp::min(max_len, (PAGESIZE - offset) as usize);
-
-    let addr = (pa + offset).as_u64() as *const _;
-    unsafe {
-        let slice: &[u8] = from_raw_parts::<u8>(addr, n);
-        let len = slice
+    let mut addr = addr;
+    let mut len = 0;
+    while len < buf.len() {
+        let base = align_down(addr, PAGESIZE);
+        let offset = addr - base;
+        let va = VirtAddr::try_new(base).ok()?;
+        let pa = map_addr(page_table, va, PteFlag::empty())?;
+        let n = cmp::min(buf.len() - len, (PAGESIZE - offset) as usize);
+        let src: &[u8] = unsafe { from_raw_parts((pa + offset).as_u64() as *const u8, n) };
+        let end = src.iter().position(|c| *c == 0);
+        let n = end.unwrap_or(n);
+        buf[len..len + n].copy_from_slice(&src[..n]);
+        len += n;
+        if end.is_some() {
+            break;
+        }
+        addr = base + PAGESIZE;
+    }
+    Some(len as u64)
+}
+
+/// Copy src to virtual address dst in the user page table.
+/// Return None if any of the destination is not mapped writable.
+pub fn copy_out(page_table: &PageTable, dst: u64, src: &[u8]) -> Option<()> {
+    let mut dst = dst;
+    let mut src = src;
+    while !src.is_empty() {
+        let base = align_down(dst, PAGESIZE);
+        let offset = dst - base;
+        let va = VirtAddr::try_new(base).ok()?;
+        let pa = map_addr(page_table, va, PteFlag::PTE_WRITE)?;
+        let n = cmp::min(src.len(), (PAGESIZE - offset) as usize);
+        unsafe {
+            copy::<u8>(src.as_ptr(), (pa + offset).as_u64() as *mut u8, n);
+        }
+        src = &src[n..];
+        dst = base + PAGESIZE;
+    }
+    Some(())
+}
+
+/// Decode the flags of a PTE as "vrwxugad", with '-' for a clear bit
+fn flag_str(flag: PteFlag) -> String {
+    [
+        (PteFlag::PTE_VALID, 'v'),
+        (PteFlag::PTE_READ, 'r'),
+        (PteFlag::PTE_WRITE, 'w'),
+        (PteFlag::PTE_EXEC, 'x'),
+        (PteFlag::PTE_USER, 'u'),
+        (PteFlag::PTE_GLOB, 'g'),
+        (PteFlag::PTE_ACCES, 'a'),
+        (PteFlag::PTE_DIRTY, 'd'),
+    ]
+    .iter()
+    .map(|(bit, c)| if flag.contains(*bit) { *c } else { '-' })
+    .collect()
+}
+
+/// Leaf entries of one level with contiguous virtual and physical
+/// addresses and the same flags, printed as one line.
+/// A run may continue into the next tables of its level.
+struct LeafRun {
+    level: PageTableLevel,
+    first: usize,
+    last: usize,
+    tables: usize,
+    va: u64,
+    pa: u64,
+    size: u64,
+    flag: PteFlag,
+    /// index of its line, reserved where the run starts
+    line: usize,
+}
+
+impl LeafRun {
+    /// Add the leaf at index i of a table if it continues the run
+    fn extend(&mut self, level: PageTableLevel, i: usize, va: u64, pte: &PageTableEntry) -> bool {
+        if self.level != level
+            || self.flag != pte.flag()
+            || self.va + self.size != va
+            || self.pa + self.size != pte.addr()
+        {
+            return false;
+        }
+        if i == 0 {
+            self.tables += 1;
+        }
+        self.last = i;
+        self.size += level.page_size();
+        true
+    }
+
+    fn format(&self) -> String {
+        let indices = match self.tables {
+            1 => format!("[{}-{}]", self.first, self.last),
+            n => format!("[{}-{} in {} tables]", self.first, self.last, n),
+        };
+        format!(
+            "{:indent$}L{} {} va {:#x}-{:#x} pa {:#x}-{:#x} {}",
+            "",
+            self.level as usize,
+            indices,
+            self.va,
+            self.va + self.size - 1,
+            self.pa,
+            self.pa + self.size - 1,
+            flag_str(self.flag),
+            indent = 2 * (ROOT_LEVEL as usize - self.level as usize)
+        )
+    }
+}
+
+fn vmprint_table(
+    page_table: &PageTable,
+    level: PageTableLevel,
+    base: u64,
+    run: &mut Option<LeafRun>,
+    lines: &mut Vec<String>,
+) {
+    for i in 0..512 {
+        let pte = &page_table[i];
+        let va = base + i as u64 * level.page_size();
+        if pte.is_leaf() {
+            if run.as_mut().map_or(false, |r| r.extend(level, i, va, pte)) {
+                continue;
+            }
+            if let Some(r) = run.take() {
+                lines[r.line] = r.format();
+            }
+            *run = Some(LeafRun {
+                level,
+                first: i,
+                last: i,
+                tables: 1,
+                va,
+                pa: pte.addr(),
+                size: level.page_size(),
+                flag: pte.flag(),
+                line: lines.len(),
+            });
+            lines.push(String::new());
+            continue;
+        }
+        if !pte.flag().contains(PteFlag::PTE_VALID) {
+            continue;
+        }
+        lines.push(format!(
+            "{:indent$}L{} [{}] va {:#x}-{:#x} table {:#x}",
+            "",
+            level as usize,
+            i,
+            va,
+            va + level.page_size() - 1,
+            pte.addr(),
+            indent = 2 * (ROOT_LEVEL as usize - level as usize)
+        ));
+        if let Some(next_level) = level.next_level() {
+            let next_table = unsafe { &*(pte.addr() as *const PageTable) };
+            vmprint_table(next_table, next_level, va, run, lines);
+        }
+    }
+}
+
+/// Format every valid PTE of page_table with its level, index,
+/// virtual range, physical address and flags, one line each.
+/// Runs of contiguous leaf entries are merged into one line,
+/// placed where the run starts even if it goes on in the next tables.
+fn vmprint_lines(page_table: &PageTable) -> Vec<String> {
+    let mut lines = vec![format!(
+        "page table {:#x}",
+        page_table as *const PageTable as u64
+    )];
+    let mut run = None;
+    vmprint_table(page_table, ROOT_LEVEL, 0, &mut run, &mut lines);
+    if let Some(r) = run {
+        lines[r.line] = r.format();
+    }
+    lines
+}
+
+/// Print the page table as formatted by vmprint_lines
+pub fn vmprint(page_table: &PageTable) {
+    for line in vmprint_lines(page_table) {
+        println!("{}", line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_pagetable() -> &'static mut PageTable {
+        unsafe { &mut *(kalloc() as *mut PageTable) }
+    }
+
+    /// Map a newly allocated page at va
+    fn map_new_page(page_table: &mut PageTable, va: u64, perm: PteFlag) -> PhysAddr {
+        let pa = PhysAddr::new(kalloc() as u64);
+        map_pages(page_table, VirtAddr::new(va), pa, PAGESIZE, perm).unwrap();
+        pa
+    }
+
+    fn user_rw() -> PteFlag {
+        PteFlag::PTE_READ | PteFlag::PTE_WRITE | PteFlag::PTE_USER
+    }
+
+    #[test_case]
+    fn map_unmap() {
+        let page_table = new_pagetable();
+        let va = VirtAddr::new(0x40_0000);
+        let pa0 = map_new_page(page_table, va.as_u64(), user_rw());
+        let pa1 = map_new_page(page_table, va.as_u64() + PAGESIZE, user_rw());
+
+        assert_eq!(map_addr(page_table, va, PteFlag::empty()), Some(pa0));
+        assert_eq!(
+            map_addr(page_table, va + PAGESIZE, PteFlag::PTE_WRITE),
+            Some(pa1)
+        );
+        assert_eq!(
+            map_addr(page_table, va + 2 * PAGESIZE, PteFlag::empty()),
+            None
+        );
+        assert!(map_pages(page_table, va, pa1, PAGESIZE, user_rw()).is_err());
+
+        unmap_pages(page_table, va, 2, true).unwrap();
+        assert_eq!(map_addr(page_table, va, PteFlag::empty()), None);
+        assert!(unmap_pages(page_table, va, 1, false).is_err());
+        free_pagetable(page_table, ROOT_LEVEL).unwrap();
+    }
+
+    #[test_case]
+    fn copy_in_str_across_pages() {
+        let page_table = new_pagetable();
+        let va = 0x40_0000;
+        map_new_page(page_table, va, user_rw());
+        let pa1 = map_new_page(page_table, va + PAGESIZE, user_rw());
+        let pa0 = map_addr(page_table, VirtAddr::new(va), PteFlag::empty()).unwrap();
+        // "abcdef" with its nul in the second page
+        unsafe {
+            copy(
+                b"abc".as_ptr(),
+                (pa0 + (PAGESIZE - 3)).as_u64() as *mut u8,
+                3,
+            );
+            copy(b"def\0".as_ptr(), pa1.as_u64() as *mut u8, 4);
+        }
+        let addr = va + PAGESIZE - 3;
+        let mut buf = [0; 16];
+        assert_eq!(copy_in_str(page_table, addr, &mut buf), Some(6));
+        assert_eq!(&buf[..6], b"abcdef");
+        // buf fills up before the second page is needed
+        assert_eq!(copy_in_str(page_table, addr, &mut buf[..2]), Some(2));
+
+        unmap_pages(page_table, VirtAddr::new(va + PAGESIZE), 1, true).unwrap();
+        assert_eq!(copy_in_str(page_table, addr, &mut buf), None);
+        unmap_pages(page_table, VirtAddr::new(va), 1, true).unwrap();
+        free_pagetable(page_table, ROOT_LEVEL).unwrap();
+    }
+
+    #[test_case]
+    fn map_unaligned() {
+        let page_table = new_pagetable();
+        let pa = PhysAddr::new(0x8800_0000);
+        // two bytes across a page boundary need two pages
+        map_pages(page_table, VirtAddr::new(0x1fff), pa, 2, user_rw()).unwrap();
+        assert_eq!(
+            map_addr(page_table, VirtAddr::new(0x1000), PteFlag::empty()),
+            Some(pa)
+        );
+        assert_eq!(
+            map_addr(page_table, VirtAddr::new(0x2000), PteFlag::empty()),
+            Some(pa + PAGESIZE)
+        );
+        unmap_pages(page_table, VirtAddr::new(0x1000), 2, false).unwrap();
+        free_pagetable(page_table, ROOT_LEVEL).unwrap();
+    }
+
+    #[test_case]
+    fn map_superpage() {
+        let page_table = new_pagetable();
+        let mega = PageTableLevel::One.page_size();
+        let va = VirtAddr::new(mega - PAGESIZE);
+        let pa = PhysAddr::new(KERNELBASE + mega - PAGESIZE);
+        // a page, then two 2 MiB superpages
+        map_pages(page_table, va, pa, 2 * mega + PAGESIZE, user_rw()).unwrap();
+        for offset in [0, PAGESIZE, mega + 5 * PAGESIZE, 2 * mega] {
+            assert_eq!(
+                map_addr(page_table, va + offset, PteFlag::empty()),
+                Some(pa + offset)
+            );
+        }
+        assert_eq!(
+            map_addr(page_table, va + 2 * mega + PAGESIZE, PteFlag::empty()),
+            None
+        );
+        // a superpage is unmapped whole, not from its middle
+        assert!(unmap_pages(page_table, va + PAGESIZE, 1, false).is_err());
+        assert!(unmap_pages(page_table, va + 2 * PAGESIZE, mega / PAGESIZE, false).is_err());
+        assert_eq!(
+            map_addr(page_table, va + PAGESIZE, PteFlag::empty()),
+            Some(pa + PAGESIZE)
+        );
+        unmap_pages(page_table, va, 2 * mega / PAGESIZE + 1, false).unwrap();
+        free_pagetable(page_table, ROOT_LEVEL).unwrap();
+    }
+
+    #[test_case]
+    fn user_pages_with_holes() {
+        let page_table = new_pagetable();
+        let va = VirtAddr::new(0x10_0000);
+        let rw = PteFlag::PTE_READ | PteFlag::PTE_WRITE;
+        map_user_page(page_table, va, rw).unwrap();
+        map_user_page(page_table, va + 2 * PAGESIZE, rw).unwrap();
+        assert!(map_user_page(page_table, va, rw).is_err());
+        assert!(map_addr(page_table, va + 2 * PAGESIZE, PteFlag::PTE_WRITE).is_some());
+        // the page in between was never populated
+        unmap_user_pages(page_table, va, 3);
+        assert_eq!(map_addr(page_table, va, PteFlag::empty()), None);
+        free_pagetable(page_table, ROOT_LEVEL).unwrap();
+    }
+
+    #[test_case]
+    fn pte_flag_str() {
+        assert_eq!(flag_str(PteFlag::empty()), "--------");
+        assert_eq!(flag_str(PteFlag::PTE_VALID | user_rw()), "vrw-u---");
+    }
+
+    #[test_case]
+    fn vmprint_runs() {
+        let page_table = new_pagetable();
+        let mega = PageTableLevel::One.page_size();
+        // four pages across two last level tables, not aligned for a superpage,
+        // then a page after a hole and a read only page
+        let va = VirtAddr::new(mega - 2 * PAGESIZE);
+        let pa = PhysAddr::new(0x8800_1000);
+        map_pages(page_table, va, pa, 4 * PAGESIZE, user_rw()).unwrap();
+        map_pages(page_table, va + 5 * PAGESIZE, pa, PAGESIZE, user_rw()).unwrap();
+        let read = PteFlag::PTE_READ | PteFlag::PTE_USER;
+        map_pages(page_table, va + 6 * PAGESIZE, pa + PAGESIZE, PAGESIZE, read).unwrap();
+
+        let lines = vmprint_lines(page_table);
+        let leaves: Vec<&str> = lines
             .iter()
-            .take_while(|c| **c != 0)
-            .zip(buf.iter_mut())
-            .map(|(a, b)| *b = *a)
-            .count();
-        Some(len as u64)
+            .map(|line| line.trim_start())
+            .filter(|line| line.starts_with("L0"))
+            .collect();
+        assert_eq!(
+            leaves,
+            [
+                "L0 [510-1 in 2 tables] va 0x1fe000-0x201fff pa 0x88001000-0x88004fff vrw-u---",
+                "L0 [3] va 0x203000-0x203fff pa 0x88001000-0x88001fff vrw-u---",
+                "L0 [4] va 0x204000-0x204fff pa 0x88002000-0x88002fff vr--u---",
+            ]
+        );
+        // the run is printed under the first table it is in
+        let run = lines
+            .iter()
+            .position(|line| line.contains("[510-1"))
+            .unwrap();
+        assert!(lines[run - 1].trim_start().starts_with("L1 [0]"));
+        assert!(lines[run + 1].trim_start().starts_with("L1 [1]"));
+
+        unmap_pages(page_table, va, 4, false).unwrap();
+        unmap_pages(page_table, va + 5 * PAGESIZE, 2, false).unwrap();
+        free_pagetable(page_table, ROOT_LEVEL).unwrap();
+    }
+
+    #[test_case]
+    fn map_permission() {
+        let page_table = new_pagetable();
+        let va = VirtAddr::new(0);
+        map_new_page(page_table, 0, PteFlag::PTE_READ | PteFlag::PTE_USER);
+        assert!(map_addr(page_table, va, PteFlag::empty()).is_some());
+        assert!(map_addr(page_table, va, PteFlag::PTE_WRITE).is_none());
+        unmap_free(page_table, PAGESIZE).unwrap();
+
+        // kernel pages are not visible to map_addr
+        let page_table = new_pagetable();
+        map_new_page(page_table, 0, PteFlag::PTE_READ | PteFlag::PTE_WRITE);
+        assert!(map_addr(page_table, va, PteFlag::empty()).is_none());
+        unmap_free(page_table, PAGESIZE).unwrap();
+    }
+
+    #[test_case]
+    fn copy_out_in() {
+        let page_table = new_pagetable();
+        map_new_page(page_table, 0, user_rw());
+        map_new_page(page_table, PAGESIZE, user_rw());
+
+        // the string crosses the page boundary
+        let addr = PAGESIZE - 3;
+        assert_eq!(copy_out(page_table, addr, b"hello\0"), Some(()));
+        let mut buf = [0u8; 16];
+        assert_eq!(copy_in_str(page_table, addr, &mut buf), Some(3));
+        assert_eq!(&buf[..3], b"hel");
+        assert_eq!(copy_in_str(page_table, PAGESIZE, &mut buf), Some(2));
+        assert_eq!(&buf[..2], b"lo");
+        assert_eq!(copy_out(page_table, 2 * PAGESIZE - 1, b"ab"), None);
+        // non-canonical addresses fail instead of panic
+        assert_eq!(copy_in_str(page_table, 1 << 40, &mut buf), None);
+        assert_eq!(copy_out(page_table, 1 << 40, b"ab"), None);
+        unmap_free(page_table, 2 * PAGESIZE).unwrap();
     }
 }
@@ -0,0 +1,246 @@
+//! Lock dependency checker, built with the `lockdep` feature
+//!
+//! Every SpinLock name is a lock class. Each hart keeps the stack of
+//! classes it holds, and acquiring class B while holding class A records
+//! the edge A -> B in a global lock order graph, with both acquisition
+//! sites. An acquisition adding an edge that closes a cycle can deadlock
+//! against the harts that recorded the other edges: the cycle is printed
+//! with its sites and the kernel panics, even if the deadlock never happened.
+//!
+//! The checker runs inside SpinLock::lock with interrupts off, so it uses
+//! fixed size tables and a bare spin flag: it must not allocate or print
+//! through a SpinLock. SleepLocks are checked only through their SpinLock.
+
+use crate::cpu::get_cpuid;
+use crate::param::NCPU;
+use crate::print::{panicked, RawUart};
+
+use core::fmt::Write;
+use core::hint::spin_loop;
+use core::panic::Location;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Maximum number of lock classes
+const MAX_CLASSES: usize = 64;
+/// Maximum number of locks held by a hart at once
+const MAX_HELD: usize = 16;
+
+type Site = &'static Location<'static>;
+
+#[derive(Clone, Copy)]
+struct Held {
+    class: usize,
+    site: Site,
+}
+
+/// Locks held by one hart, in acquisition order
+struct HeldStack {
+    locks: [Option<Held>; MAX_HELD],
+    depth: usize,
+}
+
+/// Where the edge from one class to another was first recorded
+#[derive(Clone, Copy)]
+struct Edge {
+    from_site: Site,
+    to_site: Site,
+}
+
+struct Graph {
+    classes: [&'static str; MAX_CLASSES],
+    len: usize,
+    edges: [[Option<Edge>; MAX_CLASSES]; MAX_CLASSES],
+}
+
+static mut HELD: [HeldStack; NCPU] = {
+    const EMPTY: HeldStack = HeldStack {
+        locks: [None; MAX_HELD],
+        depth: 0,
+    };
+    [EMPTY; NCPU]
+};
+
+static mut GRAPH: Graph = Graph {
+    classes: [""; MAX_CLASSES],
+    len: 0,
+    edges: [[None; MAX_CLASSES]; MAX_CLASSES],
+};
+
+/// Guards GRAPH, a SpinLock would recurse into the checker
+static GRAPH_LOCK: AtomicBool = AtomicBool::new(false);
+
+/// Cleared after the first report, so the panic path runs unchecked
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed) && !panicked()
+}
+
+fn lock_graph() -> &'static mut Graph {
+    while GRAPH_LOCK
+        .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+        spin_loop();
+    }
+    unsafe { &mut GRAPH }
+}
+
+fn unlock_graph() {
+    GRAPH_LOCK.store(false, Ordering::Release);
+}
+
+/// Stop checking and print the report header
+fn report(msg: &str) {
+    ENABLED.store(false, Ordering::Relaxed);
+    let _ = writeln!(RawUart, "lockdep: {} on hart {}", msg, get_cpuid());
+}
+
+impl Graph {
+    /// The class id of name, registered on first use
+    fn class(&mut self, name: &'static str) -> Option<usize> {
+        if let Some(class) = self.classes[..self.len].iter().position(|c| *c == name) {
+            return Some(class);
+        }
+        if self.len == MAX_CLASSES {
+            return None;
+        }
+        self.classes[self.len] = name;
+        self.len += 1;
+        Some(self.len - 1)
+    }
+
+    /// Find a path of edges from one class to another,
+    /// return its length with the classes on it stored in path.
+    fn find_path(&self, from: usize, to: usize, path: &mut [usize; MAX_CLASSES]) -> Option<usize> {
+        let mut visited = [false; MAX_CLASSES];
+        let mut parent = [0; MAX_CLASSES];
+        let mut queue = [0; MAX_CLASSES];
+        let (mut head, mut tail) = (0, 1);
+        queue[0] = from;
+        visited[from] = true;
+        while head < tail {
+            let class = queue[head];
+            head += 1;
+            if class == to {
+                // walk back to from, then reverse
+                let mut len = 0;
+                let mut c = to;
+                while c != from {
+                    path[len] = c;
+                    len += 1;
+                    c = parent[c];
+                }
+                path[len] = from;
+                len += 1;
+                path[..len].reverse();
+                return Some(len);
+            }
+            for next in 0..self.len {
+                if self.edges[class][next].is_some() && !visited[next] {
+                    visited[next] = true;
+                    parent[next] = class;
+                    queue[tail] = next;
+                    tail += 1;
+                }
+            }
+        }
+        None
+    }
+
+    fn print_edge(&self, from: usize, to: usize) {
+        if let Some(edge) = self.edges[from][to] {
+            let _ = writeln!(
+                RawUart,
+                "  {} at {} -> {} at {}",
+                self.classes[from], edge.from_site, self.classes[to], edge.to_site
+            );
+        }
+    }
+}
+
+/// Record that this hart is about to acquire the lock named name at site,
+/// panic if the acquisition can deadlock. Called with interrupts off.
+pub fn acquire(name: &'static str, site: Site) {
+    if !enabled() {
+        return;
+    }
+    let held = unsafe { &mut HELD[get_cpuid() as usize] };
+    let graph = lock_graph();
+    let class = match graph.class(name) {
+        Some(class) => class,
+        None => {
+            unlock_graph();
+            report("too many lock classes, checker disabled");
+            return;
+        }
+    };
+
+    for h in held.locks[..held.depth].iter().flatten() {
+        if graph.edges[h.class][class].is_some() {
+            continue;
+        }
+        let mut path = [0; MAX_CLASSES];
+        // the new edge h -> class closes a cycle if class already reaches h
+        if let Some(len) = graph.find_path(class, h.class, &mut path) {
+            unlock_graph();
+            report("possible deadlock");
+            let _ = writeln!(
+                RawUart,
+                "acquiring {} at {} while holding {} acquired at {}",
+                name, site, graph.classes[h.class], h.site
+            );
+            if len == 1 {
+                // two locks of the same class, another hart may take them the other way
+                panic!("lockdep: nested acquisition of {}", name);
+            }
+            let _ = writeln!(RawUart, "the reverse order was recorded at:");
+            for pair in path[..len].windows(2) {
+                graph.print_edge(pair[0], pair[1]);
+            }
+            panic!(
+                "lockdep: lock order inversion {} -> {}",
+                graph.classes[h.class], name
+            );
+        }
+        graph.edges[h.class][class] = Some(Edge {
+            from_site: h.site,
+            to_site: site,
+        });
+    }
+    unlock_graph();
+
+    if held.depth == MAX_HELD {
+        report("too many locks held, checker disabled");
+        return;
+    }
+    held.locks[held.depth] = Some(Held { class, site });
+    held.depth += 1;
+}
+
+/// Remove the lock named name from the locks held by this hart.
+/// Locks may be released out of order, the latest one of the class goes.
+pub fn release(name: &'static str) {
+    if !enabled() {
+        return;
+    }
+    let held = unsafe { &mut HELD[get_cpuid() as usize] };
+    let graph = lock_graph();
+    let class = graph.classes[..graph.len].iter().position(|c| *c == name);
+    unlock_graph();
+
+    let index = held.locks[..held.depth]
+        .iter()
+        .rposition(|h| h.map(|h| h.class) == class);
+    match index {
+        Some(index) => {
+            held.locks.copy_within(index + 1..held.depth, index);
+            held.depth -= 1;
+            held.locks[held.depth] = None;
+        }
+        None => {
+            report("releasing a lock not held");
+            panic!("lockdep: release {} not held", name);
+        }
+    }
+}
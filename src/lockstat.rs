@@ -0,0 +1,134 @@
+//! Contention statistics of SpinLocks, summed by lock name
+//!
+//! Each hart counts into its own row, written only with interrupts off
+//! while it acquires a lock, so counting does not bounce cache lines
+//! between harts. The rows are summed when the report is printed,
+//! by the lockstat syscall or control-l on the console.
+
+use crate::cpu::get_cpuid;
+use crate::param::NCPU;
+
+use alloc::string::String;
+use core::fmt::Write;
+use core::hint::spin_loop;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+/// Maximum number of lock names, later names are not counted
+const MAX_NAMES: usize = 64;
+
+/// Stat index of a lock that is not registered yet
+pub const NO_STAT: usize = usize::MAX;
+
+struct Counters {
+    /// number of acquisitions
+    acquired: AtomicU64,
+    /// acquisitions that found the lock held
+    contended: AtomicU64,
+    /// iterations spent waiting for the lock
+    spins: AtomicU64,
+}
+
+/// Counters of one name, summed over the harts
+pub struct LockStat {
+    pub name: &'static str,
+    pub acquired: u64,
+    pub contended: u64,
+    pub spins: u64,
+}
+
+static mut NAMES: [&str; MAX_NAMES] = [""; MAX_NAMES];
+static LEN: AtomicUsize = AtomicUsize::new(0);
+/// Serializes registration, a SpinLock would count itself
+static REGISTER_LOCK: AtomicBool = AtomicBool::new(false);
+
+static STATS: [[Counters; MAX_NAMES]; NCPU] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: Counters = Counters {
+        acquired: AtomicU64::new(0),
+        contended: AtomicU64::new(0),
+        spins: AtomicU64::new(0),
+    };
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ROW: [Counters; MAX_NAMES] = [ZERO; MAX_NAMES];
+    [ROW; NCPU]
+};
+
+/// Return the stat index of name, registering it on first use.
+/// Return NO_STAT if the table is full.
+pub fn register(name: &'static str) -> usize {
+    while REGISTER_LOCK
+        .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+        spin_loop();
+    }
+    let len = LEN.load(Ordering::Relaxed);
+    let names = unsafe { &mut NAMES };
+    let index = match names[..len].iter().position(|n| *n == name) {
+        Some(index) => index,
+        None if len < MAX_NAMES => {
+            names[len] = name;
+            LEN.store(len + 1, Ordering::Release);
+            len
+        }
+        None => NO_STAT,
+    };
+    REGISTER_LOCK.store(false, Ordering::Release);
+    index
+}
+
+/// Count one acquisition of the lock with stat index,
+/// which spun spins times. Called with interrupts off.
+pub fn count(index: usize, spins: u64) {
+    if index == NO_STAT {
+        return;
+    }
+    // only this hart writes its row, no atomic read-modify-write needed
+    let counters = &STATS[get_cpuid() as usize][index];
+    let add = |counter: &AtomicU64, n: u64| {
+        counter.store(counter.load(Ordering::Relaxed) + n, Ordering::Relaxed)
+    };
+    add(&counters.acquired, 1);
+    if spins > 0 {
+        add(&counters.contended, 1);
+        add(&counters.spins, spins);
+    }
+}
+
+/// Sum the counters of each name over the harts
+pub fn lock_stats() -> impl Iterator<Item = LockStat> {
+    let len = LEN.load(Ordering::Acquire);
+    (0..len).map(|index| {
+        let mut stat = LockStat {
+            name: unsafe { NAMES[index] },
+            acquired: 0,
+            contended: 0,
+            spins: 0,
+        };
+        for row in STATS.iter() {
+            let counters = &row[index];
+            stat.acquired += counters.acquired.load(Ordering::Relaxed);
+            stat.contended += counters.contended.load(Ordering::Relaxed);
+            stat.spins += counters.spins.load(Ordering::Relaxed);
+        }
+        stat
+    })
+}
+
+/// Format the statistics as a table, one lock name per line
+pub fn report() -> String {
+    let mut s = String::new();
+    let _ = write!(
+        s,
+        "{:<16} {:>12} {:>12} {:>14}",
+        "lock", "acquired", "contended", "spins"
+    );
+    for stat in lock_stats() {
+        let _ = write!(
+            s,
+            "\n{:<16} {:>12} {:>12} {:>14}",
+            stat.name, stat.acquired, stat.contended, stat.spins
+        );
+    }
+    s
+}
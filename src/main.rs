@@ -3,6 +3,9 @@
 #![feature(ptr_metadata)] // from_raw_parts in kvm.rs
 #![feature(try_trait_v2)]
 #![feature(strict_provenance)] // NonNull.addr
+#![feature(custom_test_frameworks)]
+#![test_runner(crate::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
 #![no_main]
 #![no_std]
 
@@ -10,12 +13,17 @@
 extern crate alloc;
 extern crate rv64;
 
+mod backtrace;
 mod console;
 mod cpu;
 mod disk;
+mod finisher;
 mod kalloc;
+mod ksyms;
 mod kvm;
-mod list;
+#[cfg(feature = "lockdep")]
+mod lockdep;
+mod lockstat;
 mod memorylayout;
 mod param;
 mod plic;
@@ -24,27 +32,37 @@ mod print;
 mod proc;
 mod proc_util;
 mod riscv;
+mod rtc;
 mod scheduler;
+mod sleeplock;
+mod spinlock;
 mod start;
 mod syscall;
+#[cfg(test)]
+mod testing;
+mod timer;
 mod trap;
 mod uart;
 mod virtio;
 mod vm;
+mod vma;
+
+use rrxv6_common::list;
 
 use crate::cpu::{get_cpuid, init_cpu};
-use crate::disk::{init_disk, read_disk};
-use crate::kalloc::init_heap;
+use crate::disk::init_disk;
+use crate::kalloc::{init_heap, KernelHeap};
 use crate::kvm::{init_kvm, init_page};
 use crate::plic::{init_hartplic, init_plic};
-use crate::print::println;
-use crate::proc::{init_proc, init_userproc};
+use crate::print::{halt, panicked, println};
+use crate::proc::init_userproc;
+#[cfg(test)]
+use crate::proc::spawn_kthread;
 use crate::scheduler::{get_scheduler, init_scheduler};
-use crate::trap::{init_harttrap, intr_off, intr_on};
+use crate::trap::init_harttrap;
 
 use alloc::alloc::Layout;
 use core::sync::atomic::{AtomicBool, Ordering};
-use linked_list_allocator::LockedHeap;
 use rv64::asm::sync_synchronize;
 
 #[no_mangle]
@@ -59,7 +77,6 @@ pub fn main() -> ! {
         init_heap(); // initialize physical memory allocator
         init_kvm(); // initialize kernel page table
         init_page(); // initialize virtual memory
-        init_proc(); // initialize process table
         init_harttrap(); // install kernel trap vector
         init_plic(); // initialize PLIC interrupt controller
         init_hartplic(); // ask PLIC for device interrupt
@@ -67,10 +84,18 @@ pub fn main() -> ! {
 
         init_userproc(); // create first user process
 
+        #[cfg(test)]
+        spawn_kthread("test", test_main).expect("spawn test thread"); // run the tests and power off
+
         sync_synchronize();
         KERNEL_STARTED.swap(true, Ordering::Relaxed);
     } else {
-        while !KERNEL_STARTED.load(Ordering::Relaxed) {}
+        while !KERNEL_STARTED.load(Ordering::Relaxed) {
+            // hart 0 may panic while it initializes the kernel
+            if panicked() {
+                halt();
+            }
+        }
         sync_synchronize();
         println!("hart {} starting", get_cpuid());
         init_page(); // initialize virtual memory
@@ -79,19 +104,13 @@ pub fn main() -> ! {
         init_hartplic(); // ask PLIC for device interrupt
     }
 
-    // experimentally trigger the read function with interrupt
-    // uncomment to see the effect
-    // intr_on();
-    // read_disk();
-    // intr_off();
-
     let scheduler = get_scheduler();
     // start scheduling, this function shall not return
     scheduler.schedule();
 }
 
 #[global_allocator]
-static ALLOCATOR: LockedHeap = LockedHeap::empty();
+static ALLOCATOR: KernelHeap = KernelHeap::empty();
 
 #[alloc_error_handler]
 fn alloc_error_handler(layout: Layout) -> ! {
@@ -5,6 +5,8 @@
 //! https://github.com/qemu/qemu/blob/master/hw/riscv/virt.c
 //!
 //! 00001000 -- boot ROM, provided by qemu
+//! 00100000 -- test finisher
+//! 00101000 -- goldfish RTC
 //! 02000000 -- CLINT
 //! 0C000000 -- PLIC
 //! 10000000 -- uart0
@@ -18,6 +20,7 @@
 //! end -- start of kernel page allocation area
 //! PHYSTOP -- end RAM used by the kernel
 
+use crate::param;
 use crate::riscv;
 
 // qemu virt UART registers.
@@ -28,9 +31,17 @@ pub const UART0_IRQ: u64 = 10;
 pub const VIRTIO0: u64 = 0x10001000;
 pub const VIRTIO0_IRQ: u64 = 1;
 
+// sifive test finisher, for power off and reset
+pub const FINISHER: u64 = 0x100000;
+
+// goldfish real time clock
+pub const RTC0: u64 = 0x101000;
+
 // core local interruptor (CLINT), which contains the timer
 pub const CLINT: u64 = 0x2000000;
 pub const CLINT_MTIME: u64 = 0x200BFF8;
+// mtime counts at 10 MHz on qemu virt
+pub const CLINT_FREQ: u64 = 10_000_000;
 #[inline]
 pub fn clint_mtimecmp(hart: u64) -> u64 {
     CLINT + 0x4000 + 8 * hart
@@ -54,9 +65,16 @@ pub const TRAMPOLINE: u64 = riscv::MAXVA - riscv::PAGESIZE;
 
 // map kernel stacks beneath the trampoline,
 // each surrounded by invalid guard pages.
+pub const KSTACK_SIZE: u64 = param::KSTACK_PAGES as u64 * riscv::PAGESIZE;
 #[inline]
 pub fn kstack(proc_id: u64) -> u64 {
-    TRAMPOLINE - (proc_id + 1) * 2 * riscv::PAGESIZE
+    TRAMPOLINE - (proc_id + 1) * (KSTACK_SIZE + riscv::PAGESIZE)
+}
+
+// the guard page right below the kernel stack at kstack
+#[inline]
+pub fn kstack_guard(kstack: u64) -> u64 {
+    kstack - riscv::PAGESIZE
 }
 
 // User memory layout.
@@ -66,6 +84,9 @@ pub fn kstack(proc_id: u64) -> u64 {
 //   fixed-size stack
 //   expandable heap
 //   ...
+//   mmap areas, growing down
 //   TRAPFRAME (p->trapframe, used by the trampoline)
 //   TRAMPOLINE (the same page as in the kernel)
 pub const TRAPFRAME: u64 = TRAMPOLINE - riscv::PAGESIZE;
+// mmap places areas below this address, leaving a guard page
+pub const MMAP_TOP: u64 = TRAPFRAME - riscv::PAGESIZE;
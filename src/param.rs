@@ -1,7 +1,25 @@
-pub const NPROC: usize = 2;
+include!(concat!(env!("OUT_DIR"), "/interval.rs"));
+
+// maximum number of processes, kernel stacks are mapped on demand and kept
+pub const NPROC: usize = 512;
 pub const NCPU: usize = 8;
+// number of pages in each kernel stack, not counting the guard page
+pub const KSTACK_PAGES: usize = 4;
 pub const STACK_SIZE: usize = 4096;
 pub const OS_STACK_SIZE: usize = 8192;
 pub const UART_TX_BUF_SIZE: usize = 32;
 pub const CONSOLE_BUF_SIZE: usize = 128;
 pub const LEN_PROCNAME: usize = 16;
+// maximum number of mmap areas of a process
+pub const NVMA: usize = 16;
+// number of scheduling priority levels, 0 is the highest
+pub const NPRIO: usize = 8;
+pub const DEFAULT_PRIO: usize = 4;
+// a process waiting for this many scheduling rounds is raised one level
+pub const AGING_ROUNDS: usize = 16;
+// ticks a process may run at each level before demoted by MLFQ
+pub const MLFQ_QUANTUM: [usize; NPRIO] = [1, 2, 4, 8, 16, 32, 64, 128];
+// period in ticks to move all processes back to the top level of MLFQ
+pub const MLFQ_BOOST_TICKS: u64 = 100;
+// ticks to wait for the disk to complete a request
+pub const DISK_TIMEOUT_TICKS: u64 = 10;
@@ -1,6 +1,16 @@
-use crate::trap::{pop_off, push_off};
-use crate::uart::UART;
+use crate::backtrace::backtrace;
+use crate::cpu::{get_cpuid, get_cpus, try_get_cpu};
+use crate::finisher::shutdown;
+use crate::ksyms::lookup;
+use crate::memorylayout::CLINT_FREQ;
+use crate::timer::{kick, mtime};
+use crate::trap::intr_off;
+use crate::uart::{putc_raw, UART};
+use core::fmt::{self, Write};
 use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use rv64::asm::wfi;
+use rv64::csr::sepc::Sepc;
 
 #[macro_export]
 macro_rules! println {
@@ -14,20 +24,121 @@ macro_rules! println {
 }
 
 pub fn println(s: &str) {
-    push_off();
-    {
-        let mut m_uart = UART.lock();
-        m_uart.puts(s);
-        m_uart.putc('\n');
+    if panicked() {
+        halt();
     }
-    pop_off();
+    let mut m_uart = UART.lock();
+    m_uart.puts(s);
+    m_uart.putc('\n');
+}
+
+/// Write straight to the uart registers, bypassing the UART lock
+/// that a panicked hart may hold forever. Used only on the panic path
+/// and by lockdep, which runs inside SpinLock.
+pub struct RawUart;
+
+impl Write for RawUart {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        s.bytes().for_each(putc_raw);
+        Ok(())
+    }
+}
+
+/// The hart that panicked first, NO_HART before any panic
+static PANIC_HART: AtomicU64 = AtomicU64::new(NO_HART);
+const NO_HART: u64 = u64::MAX;
+
+/// Number of harts stopped by halt
+static HALTED: AtomicUsize = AtomicUsize::new(0);
+
+/// Keeps the dumps of the harts from interleaving. A raw flag, not a
+/// SpinLock, as push_off needs the Cpu of a hart that may not have one yet.
+static DUMPING: AtomicBool = AtomicBool::new(false);
+
+/// Whether a hart has panicked, other harts halt when they see it
+pub fn panicked() -> bool {
+    PANIC_HART.load(Ordering::Acquire) != NO_HART
+}
+
+/// Print the hart id, the running process, push_count and sepc of this hart
+fn dump_hart() {
+    while DUMPING
+        .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {}
+    let id = get_cpuid();
+    let sepc = Sepc::from_read().bits();
+    match try_get_cpu() {
+        None => {
+            let _ = writeln!(RawUart, "hart {}: sepc={:x}", id, sepc);
+        }
+        Some(cpu) => {
+            let pid = unsafe { cpu.proc.as_ref() }.map(|proc| proc.pid);
+            let _ = writeln!(
+                RawUart,
+                "hart {}: pid {:?} push_count {} sepc={:x}",
+                id, pid, cpu.push_count, sepc
+            );
+        }
+    }
+    DUMPING.store(false, Ordering::Release);
+}
+
+/// Dump this hart and stop it, after another hart panicked
+pub fn halt() -> ! {
+    intr_off();
+    dump_hart();
+    HALTED.fetch_add(1, Ordering::AcqRel);
+    loop {
+        wfi();
+    }
+}
+
+/// Kick the other online harts into halt, wait for them a second at most
+fn halt_others() {
+    let id = get_cpuid();
+    let mut others = 0;
+    for cpu in get_cpus() {
+        if cpu.id != id && cpu.online.load(Ordering::Acquire) {
+            kick(cpu.id);
+            others += 1;
+        }
+    }
+    let deadline = mtime() + CLINT_FREQ;
+    while HALTED.load(Ordering::Acquire) < others && mtime() < deadline {}
 }
 
 #[panic_handler]
 fn panic(panic_info: &PanicInfo<'_>) -> ! {
-    let mut m_uart = UART.lock();
-    m_uart.puts(&format!("{}", panic_info));
-    // Note that panic will hold the lock of UART
-    // so no other process can access the UART.
-    loop {}
+    intr_off();
+    let id = get_cpuid();
+    if let Err(first) =
+        PANIC_HART.compare_exchange(NO_HART, id, Ordering::AcqRel, Ordering::Acquire)
+    {
+        let _ = writeln!(RawUart, "hart {} panicked: {}", id, panic_info);
+        if first == id {
+            // panic while panicking
+            shutdown(1);
+        }
+        halt();
+    }
+
+    let _ = writeln!(RawUart, "hart {} panicked: {}", id, panic_info);
+    if let Some(trap) = try_get_cpu().and_then(|cpu| cpu.trap.take()) {
+        let _ = writeln!(
+            RawUart,
+            "scause {:x} sepc={:x} stval={:x}",
+            trap.scause, trap.sepc, trap.stval
+        );
+    }
+    let _ = writeln!(RawUart, "backtrace:");
+    backtrace(|ra| {
+        let _ = match lookup(ra) {
+            Some((name, offset)) => writeln!(RawUart, "  {:#x} {}+{:#x}", ra, name, offset),
+            None => writeln!(RawUart, "  {:#x}", ra),
+        };
+    });
+    dump_hart();
+    halt_others();
+    shutdown(1)
 }
@@ -2,17 +2,22 @@
 
 include!(concat!(env!("OUT_DIR"), "/initcode.rs"));
 
+use crate::cpu::get_proc;
 use crate::kalloc::{kalloc, kfree};
-use crate::kvm::{clear_user_pagetable, init_user_pagetable, init_uvm};
-use crate::memorylayout::kstack;
-use crate::param::{LEN_PROCNAME, NPROC};
+use crate::kvm::{clear_user_pagetable, init_user_pagetable, init_uvm, map_kstack};
+use crate::memorylayout::{kstack, KSTACK_SIZE};
+use crate::param::{DEFAULT_PRIO, LEN_PROCNAME, NPROC};
 use crate::proc_util::{Context, TrapFrame};
 use crate::riscv::PAGESIZE;
-use crate::scheduler::get_scheduler;
-use crate::trap::usertrapret;
+use crate::scheduler::{exit_proc, get_scheduler, initial_level};
+use crate::spinlock::SpinLock;
+use crate::trap::{intr_on, usertrapret};
+use crate::vm::addr::VirtAddr;
 use crate::vm::page_table::PageTable;
+use crate::vma::Vma;
 
 use alloc::boxed::Box;
+use alloc::vec::Vec;
 use core::ptr::NonNull;
 use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
@@ -21,6 +26,8 @@ use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 pub enum ProcState {
     RUNNABLE,
     RUNNING,
+    SLEEPING,
+    EXITED,
 }
 
 pub fn get_pid() -> usize {
@@ -29,28 +36,94 @@ pub fn get_pid() -> usize {
     pid
 }
 
+/// Kernel stack slots below the trampoline, see memorylayout::kstack.
+/// A slot is taken when a process is allocated and returned when it is freed,
+/// at most NPROC processes can be alive at the same time.
+/// The stack of a slot is mapped when the slot is first used and stays mapped:
+/// sfence.vma only flushes the local hart, and another hart that ran the
+/// process may still hold the translation when the slot is reused.
+struct KStackSlots {
+    /// freed slots, their stacks are still mapped
+    free: Vec<usize>,
+    next: usize,
+}
+
+impl KStackSlots {
+    const fn new() -> Self {
+        Self {
+            free: Vec::new(),
+            next: 0,
+        }
+    }
+
+    fn alloc(&mut self) -> Option<usize> {
+        if let Some(slot) = self.free.pop() {
+            return Some(slot);
+        }
+        if self.next < NPROC {
+            map_kstack(VirtAddr::new(kstack(self.next as u64))).ok()?;
+            self.next += 1;
+            Some(self.next - 1)
+        } else {
+            None
+        }
+    }
+
+    fn free(&mut self, slot: usize) {
+        self.free.push(slot);
+    }
+}
+
+static KSTACK_SLOTS: SpinLock<KStackSlots> = SpinLock::new("kstack_slots", KStackSlots::new());
+
 pub struct Proc {
     pub state: ProcState,
     pub context: Context,
+    pub slot: usize,
     pub kstack: u64,
     pub pid: usize,
+    /// static priority, changed by nice
+    pub priority: usize,
+    /// run queue level, raised above priority by aging
+    pub level: usize,
+    /// scheduling rounds spent waiting in the run queue
+    pub age: usize,
+    /// timer ticks used since last scheduled
+    pub ticks: usize,
+    /// sleeping on chan, if non-zero
+    pub chan: usize,
+    /// WAKEUP_SEQ in scheduler when going to sleep
+    pub wakeup_seq: usize,
     pub memory_size: u64,
     pub name: [u8; LEN_PROCNAME],
     pub trapframe: NonNull<TrapFrame>,
     pub pagetable: NonNull<PageTable>,
+    /// entry of a kernel thread, which has no trapframe and pagetable
+    pub kthread: Option<fn()>,
+    /// areas mapped by mmap
+    pub vmas: Vec<Vma>,
 }
 
 impl Proc {
-    pub fn new(kstack: u64) -> Self {
+    pub fn new(slot: usize, kstack: u64) -> Self {
         Self {
             state: ProcState::RUNNABLE,
             context: Context::new(),
+            slot,
             kstack,
             pid: 0,
+            priority: DEFAULT_PRIO,
+            level: initial_level(DEFAULT_PRIO),
+            age: 0,
+            ticks: 0,
+            chan: 0,
+            wakeup_seq: 0,
             memory_size: 0,
             name: [0; LEN_PROCNAME],
             trapframe: NonNull::dangling(),
             pagetable: NonNull::dangling(),
+            kthread: None,
+            vmas: Vec::new(),
         }
     }
 
@@ -68,8 +141,15 @@ impl Proc {
         self.state = ProcState::RUNNABLE;
         self.context.reset();
         self.pid = 0;
+        self.priority = DEFAULT_PRIO;
+        self.level = initial_level(DEFAULT_PRIO);
+        self.age = 0;
+        self.ticks = 0;
+        self.chan = 0;
+        self.wakeup_seq = 0;
         self.memory_size = 0;
         self.name = [0; LEN_PROCNAME];
+        self.kthread = None;
     }
 
     pub fn set_name(&mut self, s: &str) {
@@ -79,13 +159,21 @@ impl Proc {
     }
 }
 
-/// initialize kernel process stack
-pub fn init_proc() {
-    let scheduler = get_scheduler();
-    for i in 0..NPROC {
-        let proc = Box::new(Proc::new(kstack(i as u64)));
-        scheduler.unused.push(proc)
+/// Allocate a process and a kernel stack slot, mapping its stack on first use.
+/// Return None if NPROC processes are alive or memory is exhausted.
+pub fn alloc_proc() -> Option<Box<Proc>> {
+    let slot = KSTACK_SLOTS.lock().alloc()?;
+    Some(Box::new(Proc::new(slot, kstack(slot as u64))))
+}
+
+/// Reap a process, free its user memory and return its kernel stack slot.
+pub fn free_proc(mut proc: Box<Proc>) {
+    if proc.pagetable != NonNull::dangling() {
+        proc.reset(true);
+    } else if proc.trapframe != NonNull::dangling() {
+        kfree(proc.trapframe.as_ptr() as *mut _);
     }
+    KSTACK_SLOTS.lock().free(proc.slot);
 }
 
 pub fn forkret() {
@@ -99,6 +187,35 @@ pub fn forkret() {
     }
 }
 
+/// First code run by a kernel thread, switched to by the scheduler
+fn kthread_entry() {
+    let proc = get_proc();
+    let entry = unsafe { (*proc).kthread.expect("kthread_entry: not a kernel thread") };
+    // the scheduler switches here with interrupts off
+    intr_on();
+    entry();
+    exit_proc();
+}
+
+/// Create a kernel thread running entry on its own kernel stack,
+/// scheduled like user processes. The thread exits when entry returns.
+/// Return the pid of the thread.
+pub fn spawn_kthread(name: &str, entry: fn()) -> Result<usize, &'static str> {
+    let mut proc = alloc_proc().ok_or("spawn_kthread: alloc_proc")?;
+    proc.pid = get_pid();
+    proc.set_name(name);
+    proc.kthread = Some(entry);
+
+    proc.context.reset();
+    proc.context.ra = kthread_entry as u64;
+    proc.context.sp = proc.kstack + KSTACK_SIZE;
+    proc.state = ProcState::RUNNABLE;
+
+    let pid = proc.pid;
+    get_scheduler().push(proc);
+    Ok(pid)
+}
+
 /// setup user process
 fn alloc_process(proc: &mut Proc) -> Result<(), &str> {
     // allocate memory for trapframe
@@ -112,7 +229,7 @@ fn alloc_process(proc: &mut Proc) -> Result<(), &str> {
     // forkret will return to user space
     proc.context.reset();
     proc.context.ra = forkret as u64;
-    proc.context.sp = proc.kstack + PAGESIZE;
+    proc.context.sp = proc.kstack + KSTACK_SIZE;
 
     Ok(())
 }
@@ -121,11 +238,11 @@ fn alloc_process(proc: &mut Proc) -> Result<(), &str> {
 pub fn init_userproc() {
     let scheduler = get_scheduler();
 
-    let mut proc = scheduler.unused.pop().expect("init_userproc failed");
+    let mut proc = alloc_proc().expect("init_userproc failed");
 
     match alloc_process(&mut proc) {
         Err(_s) => {
-            proc.reset(true);
+            free_proc(proc);
             panic!("init_userproc: alloc_process");
         }
         Ok(()) => {
@@ -136,6 +253,8 @@ pub fn init_userproc() {
             unsafe {
                 let pagetable = proc.pagetable.as_mut();
                 init_uvm(pagetable, &INITCODE);
+                #[cfg(feature = "vmprint")]
+                crate::kvm::vmprint(pagetable);
             }
             proc.memory_size = PAGESIZE;
 
@@ -156,8 +275,39 @@ pub fn init_userproc() {
             // set state to RUNNABLE
             proc.state = ProcState::RUNNABLE;
 
-            let mut used_list = scheduler.used.lock();
-            used_list.push(proc);
+            scheduler.push(proc);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::wait_until;
+
+    /// kernel stack slot of the test thread, stored when it runs
+    static SLOT: AtomicUsize = AtomicUsize::new(usize::MAX);
+    /// the thread waits for it, so it is alive until the test checks
+    static RELEASE: AtomicBool = AtomicBool::new(false);
+
+    fn slot_free(slot: usize) -> bool {
+        KSTACK_SLOTS.lock().free.contains(&slot)
+    }
+
+    #[test_case]
+    fn kthread_runs_and_is_reaped() {
+        let pid = spawn_kthread("kthread_test", || {
+            SLOT.store(unsafe { (*get_proc()).slot }, Ordering::Release);
+            assert!(wait_until(100, || RELEASE.load(Ordering::Acquire)));
+        })
+        .unwrap();
+        assert!(pid > 0);
+        assert!(wait_until(100, || SLOT.load(Ordering::Acquire) != usize::MAX));
+        let slot = SLOT.load(Ordering::Acquire);
+        // the thread holds its slot until it exits
+        assert!(!slot_free(slot));
+        RELEASE.store(true, Ordering::Release);
+        // the scheduler returns the slot after the thread exits
+        assert!(wait_until(100, || slot_free(slot)));
+    }
+}
@@ -1,9 +1,19 @@
+use crate::vm::addr::VA_BITS;
+use rv64::csr::satp::SatpMode;
+
 // Byte per page and big offset within a page
 pub const PAGESIZE: u64 = 4096;
 pub const PAGESHIFT: u64 = 12;
 
-// MAXVA marks the virtual address limitation
-pub const MAXVA: u64 = 1 << (9 + 9 + 9 + 12 - 1);
+// MAXVA marks the virtual address limitation,
+// one bit less than the paging mode allows to avoid sign extension
+pub const MAXVA: u64 = 1 << (VA_BITS - 1);
+
+// paging mode written to satp, the sv48 feature selects four levels
+#[cfg(not(feature = "sv48"))]
+pub const SATP_MODE: SatpMode = SatpMode::ModeSv39;
+#[cfg(feature = "sv48")]
+pub const SATP_MODE: SatpMode = SatpMode::ModeSv48;
 
 // Maximum Interrupt Count
 pub const MAX_INTERRUPT: u64 = 1024;
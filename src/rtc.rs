@@ -0,0 +1,18 @@
+//! Goldfish real time clock, as provided by qemu -machine virt.
+//! Reports nanoseconds since the Unix epoch.
+
+use crate::memorylayout::RTC0;
+
+// the registers of the goldfish rtc, 32 bits each
+const TIME_LOW: u64 = 0x00;
+const TIME_HIGH: u64 = 0x04;
+
+/// Read the wall clock time in nanoseconds since the epoch
+pub fn rtc_time() -> u64 {
+    unsafe {
+        // reading TIME_LOW latches the current TIME_HIGH
+        let low = core::ptr::read_volatile((RTC0 + TIME_LOW) as *const u32);
+        let high = core::ptr::read_volatile((RTC0 + TIME_HIGH) as *const u32);
+        ((high as u64) << 32) | low as u64
+    }
+}
@@ -1,12 +1,27 @@
-use crate::cpu::get_cpu;
+#[cfg(feature = "tickless")]
+use crate::cpu::get_cpuid;
+use crate::cpu::{get_cpu, get_cpus};
 use crate::list::List;
-use crate::proc::{Proc, ProcState};
+#[cfg(not(feature = "mlfq"))]
+use crate::param::AGING_ROUNDS;
+use crate::param::NPRIO;
+#[cfg(feature = "tickless")]
+use crate::param::TIMER_INTERVAL;
+#[cfg(feature = "mlfq")]
+use crate::param::{MLFQ_BOOST_TICKS, MLFQ_QUANTUM};
+use crate::proc::{free_proc, Proc, ProcState};
 use crate::proc_util::Context;
-use crate::trap::intr_on;
+use crate::spinlock::{SpinLock, SpinLockGuard};
+#[cfg(feature = "tickless")]
+use crate::timer::{kick, mtime, next_deadline, set_timer};
+use crate::trap::{intr_get, intr_off, intr_on, pop_off, push_off, set_kstack};
 use alloc::boxed::Box;
-use core::ptr::null_mut;
+use core::mem::replace;
+use core::ptr::{self, null_mut};
+#[cfg(feature = "mlfq")]
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use rv64::asm::wfi;
-use spin::Mutex;
 
 extern "Rust" {
     // store ctx1 and load ctx2
@@ -15,31 +30,331 @@ extern "Rust" {
 
 static mut SCHEDULER: Option<Scheduler> = None;
 
+/// Runnable processes, one FIFO list for each level, level 0 runs first.
+///
+/// The policy moving processes between levels is chosen at build time:
+/// the default priority policy below, or MLFQ with the `mlfq` feature.
+pub struct RunQueue {
+    levels: [List<Box<Proc>>; NPRIO],
+    len: usize,
+    /// pid and priority of the process running on this hart,
+    /// nice changes the priority here and it is applied on requeue
+    running: Option<(usize, usize)>,
+    /// BOOST_EPOCH when this queue was last boosted
+    #[cfg(feature = "mlfq")]
+    last_boost: u64,
+}
+
+impl RunQueue {
+    pub const fn new() -> Self {
+        const EMPTY: List<Box<Proc>> = List::new();
+        Self {
+            levels: [EMPTY; NPRIO],
+            len: 0,
+            running: None,
+            #[cfg(feature = "mlfq")]
+            last_boost: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn push(&mut self, proc: Box<Proc>) {
+        let level = proc.level;
+        self.levels[level].push(proc);
+        self.len += 1;
+    }
+
+    pub fn pop(&mut self) -> Option<Box<Proc>> {
+        self.before_pick();
+        let level = self.levels.iter().position(|list| !list.is_empty())?;
+        let mut proc = self.levels[level].pop()?;
+        self.len -= 1;
+        self.picked(level, &mut proc);
+        Some(proc)
+    }
+
+    /// Renice the queued process pid and move it to the level of its new
+    /// priority, return the new priority or None if pid is not queued here.
+    fn renice(&mut self, pid: usize, renice: impl FnOnce(&mut Proc) -> usize) -> Option<usize> {
+        for i in 0..NPRIO {
+            if let Some(mut proc) = self.levels[i].take_if(|proc| proc.pid == pid).pop() {
+                let priority = renice(&mut proc);
+                self.levels[proc.level].push(proc);
+                return Some(priority);
+            }
+        }
+        None
+    }
+}
+
+/// Priority policy
+///
+/// A process is queued at its level, which starts at its static priority.
+/// Each time a process is picked, the processes waiting in lower levels age,
+/// and one that waited AGING_ROUNDS rounds is raised one level,
+/// so low priority processes do not starve.
+#[cfg(not(feature = "mlfq"))]
+impl RunQueue {
+    fn before_pick(&mut self) {}
+
+    fn picked(&mut self, level: usize, proc: &mut Proc) {
+        self.age(level);
+        proc.level = proc.priority;
+        proc.age = 0;
+    }
+
+    /// Age the processes below level, processes are queued in FIFO order
+    /// so only the head of each level can reach AGING_ROUNDS.
+    fn age(&mut self, level: usize) {
+        for i in level + 1..NPRIO {
+            for proc in self.levels[i].iter_mut() {
+                proc.age += 1;
+            }
+            while self.levels[i]
+                .peek()
+                .map_or(false, |proc| proc.age >= AGING_ROUNDS)
+            {
+                let mut proc = self.levels[i].pop().unwrap();
+                proc.age = 0;
+                proc.level = i - 1;
+                self.levels[i - 1].push(proc);
+            }
+        }
+    }
+}
+
+/// A new process starts at the level of its static priority
+#[cfg(not(feature = "mlfq"))]
+pub const fn initial_level(priority: usize) -> usize {
+    priority
+}
+
+/// A reniced process drops what it gained by aging and is queued at the
+/// level of its new priority
+#[cfg(not(feature = "mlfq"))]
+fn reniced_level(proc: &Proc) -> usize {
+    proc.priority
+}
+
+/// Called on each timer interrupt while proc is running,
+/// return true if proc should give up the CPU.
+#[cfg(not(feature = "mlfq"))]
+pub fn quantum_expired(_proc: &mut Proc) -> bool {
+    true
+}
+
+/// Called by trap::tick with the new tick count
+#[cfg(not(feature = "mlfq"))]
+pub fn clock_tick(_tick: u64) {}
+
+/// Advanced by clock_tick when it is time to boost all processes,
+/// each run queue boosts itself when it sees a new epoch
+#[cfg(feature = "mlfq")]
+static BOOST_EPOCH: AtomicU64 = AtomicU64::new(0);
+
+/// Tick of the next boost, ticks may be skipped with tickless
+#[cfg(feature = "mlfq")]
+static NEXT_BOOST: AtomicU64 = AtomicU64::new(MLFQ_BOOST_TICKS);
+
+/// Multi-level feedback queue policy
+///
+/// A new process starts at level 0. A process using up the MLFQ_QUANTUM
+/// ticks of its level is demoted one level, a process giving up the CPU
+/// before that keeps its level. Every MLFQ_BOOST_TICKS all processes are
+/// moved back to level 0. The static priority set by nice is not used.
+#[cfg(feature = "mlfq")]
+impl RunQueue {
+    fn before_pick(&mut self) {
+        let epoch = BOOST_EPOCH.load(Ordering::Relaxed);
+        if self.last_boost != epoch {
+            self.last_boost = epoch;
+            self.boost();
+        }
+    }
+
+    fn picked(&mut self, _level: usize, proc: &mut Proc) {
+        proc.ticks = 0;
+    }
+
+    fn boost(&mut self) {
+        for i in 1..NPRIO {
+            while let Some(mut proc) = self.levels[i].pop() {
+                proc.level = 0;
+                self.levels[0].push(proc);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "mlfq")]
+pub const fn initial_level(_priority: usize) -> usize {
+    0
+}
+
+#[cfg(feature = "mlfq")]
+fn reniced_level(proc: &Proc) -> usize {
+    proc.level
+}
+
+#[cfg(feature = "mlfq")]
+pub fn quantum_expired(proc: &mut Proc) -> bool {
+    proc.ticks += 1;
+    if proc.ticks < MLFQ_QUANTUM[proc.level] {
+        return false;
+    }
+    if proc.level + 1 < NPRIO {
+        proc.level += 1;
+    }
+    true
+}
+
+/// The run queue lock may be held by the interrupted code,
+/// so only start a new epoch and boost each queue on its next pick.
+#[cfg(feature = "mlfq")]
+pub fn clock_tick(tick: u64) {
+    // called with the tick lock held, so there is one writer
+    if tick >= NEXT_BOOST.load(Ordering::Relaxed) {
+        NEXT_BOOST.store(tick + MLFQ_BOOST_TICKS, Ordering::Relaxed);
+        BOOST_EPOCH.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Each hart has its own run queue in Cpu. A process going back to the
+/// queue after its time slice stays on the same hart, a new process is
+/// placed on the least loaded hart, and a hart with an empty queue steals
+/// from its siblings before going idle.
 pub struct Scheduler {
-    pub used: Mutex<List<Box<Proc>>>,
-    pub unused: List<Box<Proc>>,
+    /// processes waiting in sleep for a wakeup on their chan
+    sleeping: SpinLock<List<Box<Proc>>>,
 }
 
+/// Bumped by every wakeup. A process records it in sleep before releasing
+/// its lock, if it has changed when the scheduler parks the process, a
+/// wakeup may have missed the process and it is made runnable again.
+static WAKEUP_SEQ: AtomicUsize = AtomicUsize::new(0);
+
 impl Scheduler {
     fn new() -> Self {
         Self {
-            used: Mutex::new(List::new()),
-            unused: List::new(),
+            sleeping: SpinLock::new("sleeping", List::new()),
         }
     }
 
+    /// Pick a process from this hart, or steal one from another hart
     pub fn next(&self) -> Option<Box<Proc>> {
-        let mut used_list = self.used.lock();
-        used_list.pop()
+        let cpu = get_cpu();
+        // release the local queue before locking a sibling's
+        let proc = cpu.run_queue.lock().pop();
+        proc.or_else(|| {
+            get_cpus()
+                .filter(|other| !ptr::eq(*other, cpu))
+                .find_map(|other| other.run_queue.lock().pop())
+        })
+    }
+
+    /// Queue a runnable process on the least loaded hart
+    pub fn push(&self, proc: Box<Proc>) {
+        push_off();
+        let cpu = get_cpus()
+            .filter(|cpu| cpu.online.load(Ordering::Relaxed))
+            .min_by_key(|cpu| cpu.run_queue.lock().len())
+            .unwrap_or_else(get_cpu);
+        cpu.run_queue.lock().push(proc);
+        // a hart idle without tick would not see the process until its deadline,
+        // a busy hart picks it up on its next tick
+        #[cfg(feature = "tickless")]
+        if cpu.id != get_cpuid() && cpu.idle.load(Ordering::SeqCst) {
+            kick(cpu.id);
+        }
+        pop_off();
+    }
+
+    /// Add value to the static priority of process pid.
+    /// Return the new priority, or None if the process is not found.
+    ///
+    /// Processes are only changed under the lock of the list holding them,
+    /// a running process gets the new priority when its hart requeues it.
+    /// A process moving between a run queue and the sleeping list is not
+    /// seen for that moment.
+    pub fn nice(&self, pid: usize, value: i64) -> Option<usize> {
+        let renice = |priority: &mut usize| {
+            *priority = (*priority as i64)
+                .saturating_add(value)
+                .clamp(0, NPRIO as i64 - 1) as usize;
+            *priority
+        };
+        let renice_proc = |proc: &mut Proc| {
+            renice(&mut proc.priority);
+            proc.level = reniced_level(proc);
+            proc.priority
+        };
+
+        for cpu in get_cpus() {
+            let mut run_queue = cpu.run_queue.lock();
+            if let Some((_, priority)) = run_queue.running.as_mut().filter(|(p, _)| *p == pid) {
+                return Some(renice(priority));
+            }
+            let priority = run_queue.renice(pid, renice_proc);
+            if priority.is_some() {
+                return priority;
+            }
+        }
+        let mut sleeping = self.sleeping.lock();
+        let priority = sleeping
+            .iter_mut()
+            .find(|proc| proc.pid == pid)
+            .map(|proc| renice_proc(proc));
+        priority
+    }
+
+    /// Whether process pid is sleeping on chan
+    #[cfg(test)]
+    pub fn sleeping_on(&self, pid: usize, chan: usize) -> bool {
+        let mut sleeping = self.sleeping.lock();
+        let found = sleeping
+            .iter_mut()
+            .any(|proc| proc.pid == pid && proc.chan == chan);
+        found
+    }
+
+    /// Wake up all processes sleeping on chan
+    pub fn wakeup(&self, chan: usize) {
+        WAKEUP_SEQ.fetch_add(1, Ordering::SeqCst);
+        let mut woken = self.sleeping.lock().take_if(|proc| proc.chan == chan);
+        while let Some(mut proc) = woken.pop() {
+            proc.state = ProcState::RUNNABLE;
+            self.push(proc);
+        }
+    }
+
+    /// Keep a process that went to sleep until it is woken up
+    fn park(&self, mut proc: Box<Proc>) {
+        let mut sleeping = self.sleeping.lock();
+        if WAKEUP_SEQ.load(Ordering::SeqCst) == proc.wakeup_seq {
+            sleeping.push(proc);
+        } else {
+            drop(sleeping);
+            proc.state = ProcState::RUNNABLE;
+            get_cpu().run_queue.lock().push(proc);
+        }
     }
 
     pub fn schedule(&self) -> ! {
+        get_cpu().online.store(true, Ordering::Relaxed);
         loop {
+            // avoid deadlock by ensuring that devices can interrupt
             intr_on();
             match self.next() {
                 Some(mut proc) => {
+                    // the process restores its own interrupt state in sched
+                    intr_off();
                     let cpu = get_cpu();
                     proc.state = ProcState::RUNNING;
+                    cpu.run_queue.lock().running = Some((proc.pid, proc.priority));
+                    set_kstack(Some(proc.kstack));
                     unsafe {
                         cpu.proc = &mut proc as *mut Box<Proc>;
                         switch(
@@ -47,18 +362,83 @@ impl Scheduler {
                             &mut proc.context as *mut Context,
                         );
                     }
-                    let mut used_list = self.used.lock();
-                    used_list.push(proc);
-                }
-                None => {
-                    intr_on();
-                    wfi();
+                    set_kstack(None);
+                    let mut run_queue = cpu.run_queue.lock();
+                    if let Some((_, priority)) = run_queue.running.take() {
+                        if priority != proc.priority {
+                            proc.priority = priority;
+                            proc.level = reniced_level(&proc);
+                        }
+                    }
+                    match proc.state {
+                        ProcState::EXITED => {
+                            drop(run_queue);
+                            free_proc(proc)
+                        }
+                        ProcState::SLEEPING => {
+                            drop(run_queue);
+                            self.park(proc)
+                        }
+                        _ => run_queue.push(proc),
+                    }
                 }
+                None => self.idle(),
             }
         }
     }
 }
 
+impl Scheduler {
+    /// Wait for an interrupt when there is nothing to run
+    #[cfg(not(feature = "tickless"))]
+    fn idle(&self) {
+        intr_on();
+        wfi();
+    }
+
+    /// Wait for an interrupt when there is nothing to run,
+    /// with the timer pushed out to the next deadline instead of every tick.
+    #[cfg(feature = "tickless")]
+    fn idle(&self) {
+        intr_off();
+        let cpu = get_cpu();
+        let hart = cpu.id;
+        // hart 0 counts ticks for sleeping processes and runs the timers
+        let deadline = match hart {
+            0 if self.has_sleepers() => None,
+            0 => Some(next_deadline().unwrap_or(u64::MAX)),
+            _ => Some(u64::MAX),
+        };
+        if let Some(deadline) = deadline {
+            set_timer(hart, deadline);
+            // a process pushed after idle is set kicks this hart
+            cpu.idle.store(true, Ordering::SeqCst);
+            if self.has_runnable() {
+                cpu.idle.store(false, Ordering::Relaxed);
+                set_timer(hart, mtime() + TIMER_INTERVAL);
+                return;
+            }
+        }
+        intr_on();
+        wfi();
+        if deadline.is_some() {
+            cpu.idle.store(false, Ordering::Relaxed);
+            set_timer(hart, mtime() + TIMER_INTERVAL);
+        }
+    }
+
+    #[cfg(feature = "tickless")]
+    fn has_sleepers(&self) -> bool {
+        !self.sleeping.lock().is_empty()
+    }
+
+    /// Whether any hart has a process to run or steal
+    #[cfg(feature = "tickless")]
+    fn has_runnable(&self) -> bool {
+        get_cpus().any(|cpu| cpu.run_queue.lock().len() > 0)
+    }
+}
+
 pub fn get_scheduler() -> &'static mut Scheduler {
     unsafe { SCHEDULER.as_mut().unwrap() }
 }
@@ -69,17 +449,65 @@ pub fn init_scheduler() {
     }
 }
 
-/// give up the CPU and return to scheduler
-pub fn yield_proc() {
+/// switch to the scheduler, leaving the running process in state
+///
+/// The process may resume on another hart, so its interrupt state and
+/// push_off nesting are saved here and restored on the hart it resumes on.
+fn sched(state: ProcState) {
+    let intena = intr_get();
+    intr_off();
+
     let cpu = get_cpu();
+    let push_count = replace(&mut cpu.push_count, 0);
+    let interrupt_base = replace(&mut cpu.interrupt_base, false);
     unsafe {
-        let mut proc = &mut *cpu.proc as &mut Box<Proc>;
+        let proc = &mut *cpu.proc as &mut Box<Proc>;
         cpu.proc = null_mut();
-        proc.state = ProcState::RUNNABLE;
+        proc.state = state;
 
         switch(
             &mut proc.context as *mut Context,
             &mut cpu.context as *mut Context,
         );
     }
+
+    let cpu = get_cpu();
+    cpu.push_count = push_count;
+    cpu.interrupt_base = interrupt_base;
+    if intena {
+        intr_on();
+    }
+}
+
+/// give up the CPU and return to scheduler
+pub fn yield_proc() {
+    sched(ProcState::RUNNABLE);
+}
+
+/// terminate the running process, the scheduler will free it
+pub fn exit_proc() -> ! {
+    sched(ProcState::EXITED);
+    panic!("exit_proc: exited process scheduled");
+}
+
+/// Atomically release the lock held by guard and sleep on chan,
+/// the lock is reacquired when woken up.
+/// Wakeups can be spurious, callers check their condition in a loop.
+pub fn sleep<'a, T>(
+    chan: usize,
+    lock: &'a SpinLock<T>,
+    guard: SpinLockGuard<'a, T>,
+) -> SpinLockGuard<'a, T> {
+    push_off();
+    let proc = unsafe { &mut **get_cpu().proc };
+    proc.chan = chan;
+    // a wakeup from now on is noticed by Scheduler::park
+    proc.wakeup_seq = WAKEUP_SEQ.load(Ordering::SeqCst);
+    drop(guard);
+    pop_off();
+
+    sched(ProcState::SLEEPING);
+
+    proc.chan = 0;
+    lock.lock()
 }
@@ -0,0 +1,152 @@
+//! Long-term lock for processes
+//!
+//! A process waiting for a SleepLock sleeps instead of spinning, so the
+//! lock can be held across blocking I/O. Only a process can acquire it,
+//! interrupt handlers and the scheduler must use SpinLock.
+
+use crate::cpu::get_proc;
+use crate::scheduler::{get_scheduler, sleep};
+use crate::spinlock::SpinLock;
+
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+
+pub struct SleepLock<T> {
+    name: &'static str,
+    /// pid of the process holding the lock
+    owner: SpinLock<Option<usize>>,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for SleepLock<T> {}
+unsafe impl<T: Send> Send for SleepLock<T> {}
+
+pub struct SleepLockGuard<'a, T> {
+    lock: &'a SleepLock<T>,
+}
+
+/// pid of the running process
+fn current_pid(name: &str) -> usize {
+    let proc = get_proc();
+    if proc.is_null() {
+        panic!("sleep lock {}: no process", name);
+    }
+    unsafe { (*proc).pid }
+}
+
+impl<T> SleepLock<T> {
+    pub const fn new(name: &'static str, data: T) -> Self {
+        Self {
+            name,
+            owner: SpinLock::new(name, None),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// The data behind an exclusive reference, which needs no locking.
+    /// Used to initialize the data before there is a process.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    /// Sleep until the lock is free and take it for the running process.
+    /// Panic if the running process already holds the lock.
+    #[track_caller]
+    pub fn lock(&self) -> SleepLockGuard<'_, T> {
+        let pid = current_pid(self.name);
+        let mut owner = self.owner.lock();
+        if *owner == Some(pid) {
+            panic!("acquire {}: held by pid {}", self.name, pid);
+        }
+        while owner.is_some() {
+            owner = sleep(self.chan(), &self.owner, owner);
+        }
+        *owner = Some(pid);
+        SleepLockGuard { lock: self }
+    }
+
+    /// Whether the running process holds the lock
+    pub fn holding(&self) -> bool {
+        let pid = current_pid(self.name);
+        *self.owner.lock() == Some(pid)
+    }
+
+    /// sleep channel of processes waiting for the lock
+    pub fn chan(&self) -> usize {
+        self as *const Self as usize
+    }
+}
+
+impl<T> Deref for SleepLockGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> DerefMut for SleepLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for SleepLockGuard<'_, T> {
+    fn drop(&mut self) {
+        let lock = self.lock;
+        let mut owner = lock.owner.lock();
+        if *owner != Some(current_pid(lock.name)) {
+            panic!("release {}: not held by this process", lock.name);
+        }
+        *owner = None;
+        drop(owner);
+        get_scheduler().wakeup(lock.chan());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proc::spawn_kthread;
+    use crate::testing::wait_until;
+
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test_case]
+    fn owner_pid() {
+        let lock = SleepLock::new("sleeplock_test", 0);
+        let pid = current_pid(lock.name());
+        let guard = lock.lock();
+        assert!(lock.holding());
+        assert_eq!(*lock.owner.lock(), Some(pid));
+        drop(guard);
+        assert!(!lock.holding());
+        assert_eq!(*lock.owner.lock(), None);
+    }
+
+    static CONTENDED: SleepLock<u32> = SleepLock::new("sleeplock_test", 0);
+    /// pid + 1 of the owner seen by the thread once it got the lock
+    static OWNER: AtomicUsize = AtomicUsize::new(0);
+
+    fn contend() {
+        let mut guard = CONTENDED.lock();
+        *guard += 1;
+        let owner = CONTENDED.owner.lock().unwrap();
+        OWNER.store(owner + 1, Ordering::Release);
+    }
+
+    #[test_case]
+    fn contended_sleeper_woken() {
+        let guard = CONTENDED.lock();
+        let pid = spawn_kthread("sleeplock_test", contend).unwrap();
+        // the thread sleeps on the lock instead of spinning
+        assert!(wait_until(100, || get_scheduler().sleeping_on(pid, CONTENDED.chan())));
+        assert_eq!(OWNER.load(Ordering::Acquire), 0);
+        drop(guard);
+        assert!(wait_until(100, || OWNER.load(Ordering::Acquire) == pid + 1));
+        assert_eq!(*CONTENDED.lock(), 1);
+    }
+}
@@ -0,0 +1,124 @@
+//! Spin lock that turns off interrupts while it is held
+//!
+//! Acquiring calls push_off and releasing calls pop_off, so an interrupt
+//! handler cannot spin on a lock that the code it interrupted holds.
+//! The holding hart is recorded to catch recursive acquisition.
+//! Acquisitions and the time spent spinning are counted in lockstat.
+
+use crate::cpu::get_cpuid;
+#[cfg(feature = "lockdep")]
+use crate::lockdep;
+use crate::lockstat::{self, NO_STAT};
+use crate::trap::{pop_off, push_off};
+
+use core::cell::UnsafeCell;
+use core::hint::spin_loop;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+// holder of a free lock
+const NO_HART: u64 = u64::MAX;
+
+pub struct SpinLock<T> {
+    name: &'static str,
+    locked: AtomicBool,
+    hart: AtomicU64,
+    /// index of the name in lockstat, registered on first acquisition
+    stat: AtomicUsize,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for SpinLock<T> {}
+unsafe impl<T: Send> Send for SpinLock<T> {}
+
+pub struct SpinLockGuard<'a, T> {
+    lock: &'a SpinLock<T>,
+}
+
+impl<T> SpinLock<T> {
+    pub const fn new(name: &'static str, data: T) -> Self {
+        Self {
+            name,
+            locked: AtomicBool::new(false),
+            hart: AtomicU64::new(NO_HART),
+            stat: AtomicUsize::new(NO_STAT),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Spin until the lock is acquired, with interrupts off until it is released.
+    /// Panic if this hart already holds the lock.
+    #[track_caller]
+    pub fn lock(&self) -> SpinLockGuard<'_, T> {
+        push_off();
+        if self.holding() {
+            panic!("acquire {}: held by this hart", self.name);
+        }
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire(self.name, core::panic::Location::caller());
+        // test and test-and-set: wait with loads, which keep the line shared,
+        // and count only the spins that saw the lock held, not weak cas failures
+        let mut spins = 0;
+        loop {
+            while self.locked.load(Ordering::Relaxed) {
+                spins += 1;
+                spin_loop();
+            }
+            if self
+                .locked
+                .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                break;
+            }
+        }
+        self.hart.store(get_cpuid(), Ordering::Relaxed);
+        lockstat::count(self.stat_index(), spins);
+        SpinLockGuard { lock: self }
+    }
+
+    fn stat_index(&self) -> usize {
+        let index = self.stat.load(Ordering::Relaxed);
+        if index != NO_STAT {
+            return index;
+        }
+        let index = lockstat::register(self.name);
+        self.stat.store(index, Ordering::Relaxed);
+        index
+    }
+
+    /// Whether this hart holds the lock, call with interrupts off
+    pub fn holding(&self) -> bool {
+        self.locked.load(Ordering::Relaxed) && self.hart.load(Ordering::Relaxed) == get_cpuid()
+    }
+}
+
+impl<T> Deref for SpinLockGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> DerefMut for SpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for SpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        if !self.lock.holding() {
+            panic!("release {}: not held by this hart", self.lock.name);
+        }
+        #[cfg(feature = "lockdep")]
+        lockdep::release(self.lock.name);
+        self.lock.hart.store(NO_HART, Ordering::Relaxed);
+        self.lock.locked.store(false, Ordering::Release);
+        pop_off();
+    }
+}
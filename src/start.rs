@@ -23,8 +23,6 @@ static STACK0: [u8; param::OS_STACK_SIZE * param::NCPU] = [0; param::OS_STACK_SI
 #[no_mangle]
 static mut TIMER_SCRATCH: [[u64; 5]; param::NCPU] = [[0u64; 5]; param::NCPU];
 
-const INTERVAL: u64 = 1000000;
-
 extern "C" {
     fn timervec();
 }
@@ -36,12 +34,12 @@ fn init_timer() {
     let mtimecmpaddr = memorylayout::clint_mtimecmp(mhartid);
     unsafe {
         let val = core::ptr::read_volatile(memorylayout::CLINT_MTIME as *mut u64);
-        core::ptr::write_volatile(mtimecmpaddr as *mut u64, val + INTERVAL);
+        core::ptr::write_volatile(mtimecmpaddr as *mut u64, val + param::TIMER_INTERVAL);
     }
     unsafe {
         let arr = &mut TIMER_SCRATCH[mhartid as usize];
         arr[3] = mtimecmpaddr;
-        arr[4] = INTERVAL;
+        arr[4] = param::TIMER_INTERVAL;
         Mscratch::from_bits(arr.as_ptr() as u64).write();
     }
 
@@ -1,14 +1,47 @@
 use crate::cpu::get_proc;
-use crate::kvm::copy_in_str;
+use crate::finisher::{reboot, shutdown};
+use crate::kvm::{copy_in_str, copy_out, vmprint};
+use crate::lockstat;
 use crate::println;
+use crate::riscv::PAGESIZE;
+use crate::rtc::rtc_time;
+use crate::scheduler::get_scheduler;
+use crate::timer::mtime_ns;
+use crate::trap::{sleep_ticks, uptime};
+use crate::vma::{fault_in, mmap, munmap};
 
 use alloc::string::String;
+use core::cmp;
+use core::mem::size_of;
+use core::slice::from_raw_parts;
 use lazy_static::lazy_static;
 
-const SYSCALL_NUM: usize = 1;
+const SYSCALL_NUM: usize = 11;
 type SyscallEntry = fn() -> u64;
 lazy_static! {
-    static ref SYSCALLS: [SyscallEntry; SYSCALL_NUM] = [syscall_write];
+    static ref SYSCALLS: [SyscallEntry; SYSCALL_NUM] = [
+        syscall_write,
+        syscall_nice,
+        syscall_uptime,
+        syscall_sleep,
+        syscall_clock_gettime,
+        syscall_shutdown,
+        syscall_reboot,
+        syscall_lockstat,
+        syscall_vmprint,
+        syscall_mmap,
+        syscall_munmap,
+    ];
+}
+
+// clock ids of clock_gettime
+const CLOCK_REALTIME: u64 = 0;
+const CLOCK_MONOTONIC: u64 = 1;
+
+#[repr(C)]
+struct Timespec {
+    tv_sec: u64,
+    tv_nsec: u64,
 }
 
 #[allow(dead_code)]
@@ -40,6 +73,8 @@ fn get_arg(n: ArgIndex) -> u64 {
 fn get_str(n: ArgIndex, buf: &mut [u8]) -> u64 {
     let addr = get_arg(n);
     let proc = get_proc();
+    // the string may be in mmap pages not touched yet
+    unsafe { fault_in(&mut *proc, addr, buf.len() as u64) };
     let page_table = unsafe { (*proc).pagetable.as_mut() };
     match copy_in_str(page_table, addr, buf) {
         None => u64::MAX,
@@ -47,13 +82,127 @@ fn get_str(n: ArgIndex, buf: &mut [u8]) -> u64 {
     }
 }
 
+/// write(len, buf): print the string at buf, up to len bytes or its nul.
+/// Returns the number of bytes written.
 fn syscall_write() -> u64 {
     let len = get_arg(ArgIndex::A0);
-    let mut buf = vec![0; len as usize];
-    get_str(ArgIndex::A1, &mut buf);
-    println!("{}", String::from_utf8_lossy(&buf));
-    // FIXME the real write size
-    len
+    // get_str copies at most a page
+    let mut buf = vec![0; cmp::min(len, PAGESIZE) as usize];
+    let n = get_str(ArgIndex::A1, &mut buf);
+    if n == u64::MAX {
+        return u64::MAX;
+    }
+    println!("{}", String::from_utf8_lossy(&buf[..n as usize]));
+    n
+}
+
+/// nice(pid, value): add value to the priority of process pid,
+/// a larger priority runs less often. Only the caller's own priority
+/// can be raised with a negative value.
+/// Returns the new priority, or -1 if there is no such process
+/// or the value is not allowed.
+fn syscall_nice() -> u64 {
+    let pid = get_arg(ArgIndex::A0) as usize;
+    let value = get_arg(ArgIndex::A1) as i64;
+    if value < 0 && pid != unsafe { (*get_proc()).pid } {
+        return u64::MAX;
+    }
+    match get_scheduler().nice(pid, value) {
+        Some(priority) => priority as u64,
+        None => u64::MAX,
+    }
+}
+
+/// uptime(): return the number of timer ticks since boot
+fn syscall_uptime() -> u64 {
+    uptime()
+}
+
+/// sleep(n): block the caller for n timer ticks
+fn syscall_sleep() -> u64 {
+    let n = get_arg(ArgIndex::A0);
+    sleep_ticks(n);
+    0
+}
+
+/// clock_gettime(clockid, tp): store the time of clock into the timespec at tp.
+/// CLOCK_REALTIME is the time since the epoch, CLOCK_MONOTONIC the time since boot.
+/// Returns 0, or -1 for an unknown clock or a bad address.
+fn syscall_clock_gettime() -> u64 {
+    let ns = match get_arg(ArgIndex::A0) {
+        CLOCK_REALTIME => rtc_time(),
+        CLOCK_MONOTONIC => mtime_ns(),
+        _ => return u64::MAX,
+    };
+    let ts = Timespec {
+        tv_sec: ns / 1_000_000_000,
+        tv_nsec: ns % 1_000_000_000,
+    };
+    let addr = get_arg(ArgIndex::A1);
+    let proc = get_proc();
+    unsafe { fault_in(&mut *proc, addr, size_of::<Timespec>() as u64) };
+    let page_table = unsafe { (*proc).pagetable.as_ref() };
+    let buf = unsafe { from_raw_parts(&ts as *const Timespec as *const u8, size_of::<Timespec>()) };
+    match copy_out(page_table, addr, buf) {
+        Some(()) => 0,
+        None => u64::MAX,
+    }
+}
+
+/// shutdown(code): power off the machine, qemu exits with status code.
+/// Codes the finisher cannot hold are clamped, so they still read as a failure.
+fn syscall_shutdown() -> u64 {
+    let code = get_arg(ArgIndex::A0);
+    println!("shutdown with code {}", code);
+    shutdown(cmp::min(code, u16::MAX as u64) as u16)
+}
+
+/// reboot(): reset the machine
+fn syscall_reboot() -> u64 {
+    println!("reboot");
+    reboot()
+}
+
+/// lockstat(): print the acquisitions, contended acquisitions
+/// and spin iterations of each lock name to the console
+fn syscall_lockstat() -> u64 {
+    println!("{}", lockstat::report());
+    0
+}
+
+/// vmprint(): print the page table of the caller to the console
+fn syscall_vmprint() -> u64 {
+    let proc = get_proc();
+    vmprint(unsafe { (*proc).pagetable.as_ref() });
+    0
+}
+
+/// mmap(addr, len, prot, flags, fd, off): map len bytes of memory,
+/// populated on first access. Only MAP_ANONYMOUS mappings are supported,
+/// addr is a hint that is ignored and fd and off are not used.
+/// Returns the start address, or -1 for error.
+fn syscall_mmap() -> u64 {
+    let len = get_arg(ArgIndex::A1);
+    let prot = get_arg(ArgIndex::A2);
+    let flags = get_arg(ArgIndex::A3);
+    let proc = get_proc();
+    match mmap(unsafe { &mut *proc }, len, prot, flags) {
+        Ok(addr) => addr,
+        Err(_) => u64::MAX,
+    }
+}
+
+/// munmap(addr, len): remove the mappings of len bytes from addr.
+/// Returns 0, or -1 if addr is not page aligned, len is 0
+/// or splitting an area would need more than NVMA areas.
+fn syscall_munmap() -> u64 {
+    let addr = get_arg(ArgIndex::A0);
+    let len = get_arg(ArgIndex::A1);
+    let proc = get_proc();
+    match munmap(unsafe { &mut *proc }, addr, len) {
+        Ok(()) => 0,
+        Err(_) => u64::MAX,
+    }
 }
 
 pub fn syscall() {
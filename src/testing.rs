@@ -0,0 +1,43 @@
+//! In-kernel test framework
+//!
+//! `cargo test` builds a test kernel whose main runs every `#[test_case]`
+//! in a kernel thread after the kernel is initialized, then powers off qemu.
+//! The tests run with the scheduler and the tick, so they can sleep.
+//! A failed test panics and the panic handler exits qemu with status 1.
+
+use crate::finisher::shutdown;
+use crate::print::println;
+use crate::trap::sleep_ticks;
+
+pub trait Testable {
+    fn run(&self);
+}
+
+impl<T: Fn()> Testable for T {
+    fn run(&self) {
+        println!("test {} ...", core::any::type_name::<T>());
+        self();
+        println("ok");
+    }
+}
+
+/// Sleep a tick at a time until cond holds, at most ticks ticks.
+/// Return whether cond holds.
+pub fn wait_until(ticks: u64, cond: impl Fn() -> bool) -> bool {
+    for _ in 0..ticks {
+        if cond() {
+            return true;
+        }
+        sleep_ticks(1);
+    }
+    cond()
+}
+
+pub fn test_runner(tests: &[&dyn Testable]) {
+    println!("running {} tests", tests.len());
+    for test in tests {
+        test.run();
+    }
+    println!("test result: ok. {} passed", tests.len());
+    shutdown(0);
+}
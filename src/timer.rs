@@ -0,0 +1,238 @@
+//! Kernel timers
+//!
+//! One-shot and periodic callbacks keyed off CLINT_MTIME.
+//! Pending timers are kept sorted by deadline and expired ones are run
+//! from the timer interrupt on hart 0, so the resolution is one tick.
+//! Callbacks run in interrupt context and must not sleep.
+
+use crate::memorylayout::{clint_mtimecmp, CLINT_FREQ, CLINT_MTIME};
+use crate::spinlock::SpinLock;
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use core::sync::atomic::{AtomicU64, Ordering};
+use lazy_static::lazy_static;
+
+type Callback = Box<dyn FnMut() + Send>;
+
+/// Handle to cancel a timer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TimerId(u64);
+
+struct Timer {
+    callback: Callback,
+    /// re-armed every period if set
+    period: Option<u64>,
+}
+
+struct Timers {
+    /// pending timers ordered by (deadline, id)
+    queue: BTreeMap<(u64, TimerId), Timer>,
+    /// the periodic timer whose callback is running, one-shot timers
+    /// are not recorded as they cannot be cancelled once taken to run
+    running: Option<TimerId>,
+    /// the running timer was cancelled by its callback or another hart
+    running_cancelled: bool,
+}
+
+lazy_static! {
+    static ref TIMERS: SpinLock<Timers> = SpinLock::new(
+        "timers",
+        Timers {
+            queue: BTreeMap::new(),
+            running: None,
+            running_cancelled: false,
+        }
+    );
+}
+
+fn next_id() -> TimerId {
+    static ID_GENERATOR: AtomicU64 = AtomicU64::new(0);
+    TimerId(ID_GENERATOR.fetch_add(1, Ordering::Relaxed))
+}
+
+/// Read the machine timer, counting at the CLINT frequency
+pub fn mtime() -> u64 {
+    unsafe { core::ptr::read_volatile(CLINT_MTIME as *const u64) }
+}
+
+/// Nanoseconds since boot, from the machine timer
+pub fn mtime_ns() -> u64 {
+    mtime() * (1_000_000_000 / CLINT_FREQ)
+}
+
+/// Program the next timer interrupt of hart at mtime deadline.
+/// timervec adds TIMER_INTERVAL to it when it fires.
+pub fn set_timer(hart: u64, deadline: u64) {
+    unsafe { core::ptr::write_volatile(clint_mtimecmp(hart) as *mut u64, deadline) }
+}
+
+/// Make hart take a timer interrupt now, waking it up from wfi
+pub fn kick(hart: u64) {
+    set_timer(hart, mtime());
+}
+
+fn insert(deadline: u64, timer: Timer) -> TimerId {
+    let id = next_id();
+    TIMERS.lock().queue.insert((deadline, id), timer);
+    id
+}
+
+/// Run callback once when mtime reaches deadline
+pub fn add_timer(deadline: u64, callback: impl FnMut() + Send + 'static) -> TimerId {
+    let timer = Timer {
+        callback: Box::new(callback),
+        period: None,
+    };
+    insert(deadline, timer)
+}
+
+/// Run callback every period of mtime, starting one period from now
+pub fn add_periodic_timer(period: u64, callback: impl FnMut() + Send + 'static) -> TimerId {
+    assert!(period > 0, "add_periodic_timer: zero period");
+    let timer = Timer {
+        callback: Box::new(callback),
+        period: Some(period),
+    };
+    insert(mtime() + period, timer)
+}
+
+/// Cancel a pending timer, return false if it has already fired
+/// or is firing. A periodic timer can also be cancelled while its
+/// callback runs, even from the callback, and is not re-armed.
+pub fn cancel_timer(id: TimerId) -> bool {
+    let mut timers = TIMERS.lock();
+    let key = timers
+        .queue
+        .keys()
+        .find(|(_, key_id)| *key_id == id)
+        .copied();
+    let cancelled = match key {
+        Some(key) => timers.queue.remove(&key).is_some(),
+        None if timers.running == Some(id) => {
+            timers.running_cancelled = true;
+            true
+        }
+        None => false,
+    };
+    cancelled
+}
+
+/// Deadline of the earliest pending timer
+pub fn next_deadline() -> Option<u64> {
+    TIMERS
+        .lock()
+        .queue
+        .keys()
+        .next()
+        .map(|(deadline, _)| *deadline)
+}
+
+/// Run the callbacks of expired timers, called from the timer interrupt.
+/// The lock is released while a callback runs so it can add or cancel timers.
+pub fn run_timers() {
+    let now = mtime();
+    loop {
+        let mut timers = TIMERS.lock();
+        let key = match timers.queue.keys().next() {
+            Some(&key) if key.0 <= now => key,
+            _ => break,
+        };
+        let (deadline, id) = key;
+        let mut timer = timers.queue.remove(&key).unwrap();
+        timers.running = timer.period.map(|_| id);
+        timers.running_cancelled = false;
+        drop(timers);
+
+        (timer.callback)();
+
+        let mut timers = TIMERS.lock();
+        timers.running = None;
+        if let Some(period) = timer.period {
+            if !timers.running_cancelled {
+                // skip the periods missed while interrupts were off
+                let mut next = deadline + period;
+                while next <= now {
+                    next += period;
+                }
+                timers.queue.insert((next, id), timer);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::param::TIMER_INTERVAL;
+    use crate::testing::wait_until;
+    use crate::trap::sleep_ticks;
+
+    use alloc::sync::Arc;
+    use alloc::vec::Vec;
+    use core::sync::atomic::AtomicBool;
+
+    #[test_case]
+    fn deadline_order() {
+        let fired = Arc::new(SpinLock::new("timer_test", Vec::with_capacity(3)));
+        // holding the lock keeps interrupts off, nothing fires while adding
+        let guard = fired.lock();
+        let now = mtime();
+        for &(n, ticks) in [(2, 2), (1, 1), (3, 2)].iter() {
+            let fired = fired.clone();
+            add_timer(now + ticks * TIMER_INTERVAL, move || fired.lock().push(n));
+        }
+        drop(guard);
+        assert!(wait_until(10, || fired.lock().len() == 3));
+        // equal deadlines fire in the order they were added
+        assert_eq!(*fired.lock(), [1, 2, 3]);
+    }
+
+    #[test_case]
+    fn periodic_rearm() {
+        let count = Arc::new(AtomicU64::new(0));
+        let counter = count.clone();
+        let id = add_periodic_timer(TIMER_INTERVAL, move || {
+            counter.fetch_add(1, Ordering::Relaxed);
+        });
+        assert!(wait_until(20, || count.load(Ordering::Relaxed) >= 3));
+        assert!(cancel_timer(id));
+        let n = count.load(Ordering::Relaxed);
+        sleep_ticks(3);
+        assert_eq!(count.load(Ordering::Relaxed), n);
+        assert!(!cancel_timer(id));
+    }
+
+    #[test_case]
+    fn cancel_before_expiry() {
+        let fired = Arc::new(AtomicBool::new(false));
+        let flag = fired.clone();
+        let id = add_timer(mtime() + 2 * TIMER_INTERVAL, move || {
+            flag.store(true, Ordering::Relaxed)
+        });
+        assert!(cancel_timer(id));
+        assert!(!cancel_timer(id));
+        sleep_ticks(4);
+        assert!(!fired.load(Ordering::Relaxed));
+    }
+
+    #[test_case]
+    fn cancel_fired_one_shot() {
+        // the callback tries to cancel its own one-shot timer
+        let state = Arc::new(SpinLock::new("timer_test", (None, None)));
+        let callback_state = state.clone();
+        let mut guard = state.lock();
+        let id = add_timer(mtime() + TIMER_INTERVAL, move || {
+            let id = callback_state.lock().0;
+            if let Some(id) = id {
+                let cancelled = cancel_timer(id);
+                callback_state.lock().1 = Some(cancelled);
+            }
+        });
+        guard.0 = Some(id);
+        drop(guard);
+        assert!(wait_until(10, || state.lock().1.is_some()));
+        assert_eq!(state.lock().1, Some(false));
+        assert!(!cancel_timer(id));
+    }
+}
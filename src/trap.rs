@@ -1,4 +1,4 @@
-use rv64::csr::satp::{Satp, SatpMode};
+use rv64::csr::satp::Satp;
 use rv64::csr::scause::Scause;
 use rv64::csr::sepc::Sepc;
 use rv64::csr::sip::Sip;
@@ -8,23 +8,64 @@ use rv64::csr::stvec::Stvec;
 use rv64::register::tp;
 
 use alloc::boxed::Box;
+#[cfg(feature = "tickless")]
+use core::cmp;
+use core::fmt::Write;
 use lazy_static::lazy_static;
-use spin::Mutex;
 
 use crate::cpu::{get_cpu, get_cpuid, get_proc};
-use crate::memorylayout::{TRAMPOLINE, TRAPFRAME, UART0_IRQ, VIRTIO0_IRQ};
+use crate::disk::disk_intr;
+use crate::memorylayout::{
+    kstack_guard, KSTACK_SIZE, TRAMPOLINE, TRAPFRAME, UART0_IRQ, VIRTIO0_IRQ,
+};
+#[cfg(feature = "tickless")]
+use crate::param::TIMER_INTERVAL;
+use crate::param::{NCPU, OS_STACK_SIZE};
 use crate::plic::{Plic, PlicContext};
+use crate::print::{halt, panicked, RawUart};
 use crate::println;
 use crate::proc::{Proc, ProcState};
-use crate::riscv::{Exception, Interrupt, PAGESIZE};
-use crate::scheduler::yield_proc;
+use crate::riscv::{Exception, Interrupt, SATP_MODE};
+use crate::scheduler::{clock_tick, exit_proc, get_scheduler, quantum_expired, sleep, yield_proc};
+use crate::spinlock::SpinLock;
 use crate::syscall::syscall;
+#[cfg(feature = "tickless")]
+use crate::timer::mtime;
+use crate::timer::run_timers;
 use crate::uart::UART;
+use crate::vma::handle_page_fault;
 
 lazy_static! {
-    static ref TICK: Mutex<u64> = Mutex::new(0);
+    static ref TICK: SpinLock<u64> = SpinLock::new("tick", 0);
 }
 
+/// Stack used by kernelvec when the kernel stack overflows,
+/// with OS_STACK_SIZE bytes for each hart.
+#[no_mangle]
+static mut TRAP_STACK: [u8; OS_STACK_SIZE * NCPU] = [0; OS_STACK_SIZE * NCPU];
+
+/// What kernelvec needs to catch a kernel stack overflow before it saves
+/// registers on the stack, 32 bytes for each hart, see kernelvec.S.
+#[repr(C, align(32))]
+struct KStackCheck {
+    /// guard page of the kernel stack in use, 0 on the boot stack
+    guard: u64,
+    /// top of this hart's TRAP_STACK
+    trap_sp: u64,
+    /// t1 is saved here during the check
+    t1: u64,
+}
+
+#[no_mangle]
+static mut KSTACK_CHECK: [KStackCheck; NCPU] = {
+    const EMPTY: KStackCheck = KStackCheck {
+        guard: 0,
+        trap_sp: 0,
+        t1: 0,
+    };
+    [EMPTY; NCPU]
+};
+
 extern "C" {
     fn kernelvec();
     fn uservec();
@@ -34,9 +75,22 @@ extern "C" {
 
 // setup to take exceptions and traps in supervisor mode
 pub fn init_harttrap() {
+    let hart = get_cpuid() as usize;
+    unsafe {
+        KSTACK_CHECK[hart].trap_sp =
+            TRAP_STACK.as_ptr() as u64 + ((hart + 1) * OS_STACK_SIZE) as u64;
+    }
     Stvec::from_bits(kernelvec as u64).write();
 }
 
+/// Tell kernelvec the kernel stack this hart switches to,
+/// None for the scheduler on the boot stack. Call with interrupts off.
+pub fn set_kstack(kstack: Option<u64>) {
+    unsafe {
+        KSTACK_CHECK[get_cpuid() as usize].guard = kstack.map_or(0, kstack_guard);
+    }
+}
+
 /// enable device interrupt
 pub fn intr_on() {
     let mut sstatus = Sstatus::from_read();
@@ -60,31 +114,26 @@ pub fn intr_get() -> bool {
 /// push_off, like intr_off but required same number of pop_off to make interrupt on.
 pub fn push_off() {
     let intr = intr_get();
-    let cpu = get_cpu();
-    let mut push_count = cpu.push_count.lock();
     intr_off();
-    if *push_count == 0 {
-        let mut base = cpu.interrupt_base.lock();
-        *base = intr;
+    let cpu = get_cpu();
+    if cpu.push_count == 0 {
+        cpu.interrupt_base = intr;
     }
-    *push_count = *push_count + 1;
+    cpu.push_count += 1;
 }
 
 /// pop_off, cancel one push_off
 /// Calling pop_off without push_off will panic
 pub fn pop_off() {
-    let cpu = get_cpu();
-    let intr = intr_get();
-    let mut push_count = cpu.push_count.lock();
-    let interrupt_base = cpu.interrupt_base.lock();
-    if intr {
+    if intr_get() {
         panic!("pop_off: interruptible");
     }
-    if *push_count < 1 {
+    let cpu = get_cpu();
+    if cpu.push_count < 1 {
         panic!("pop_off: stack empty");
     }
-    *push_count = *push_count - 1;
-    if *push_count == 0 && *interrupt_base {
+    cpu.push_count -= 1;
+    if cpu.push_count == 0 && cpu.interrupt_base {
         intr_on();
     }
 }
@@ -100,7 +149,7 @@ fn handle_external_interrupt() {
             uart.handle_interrupt();
         }
         VIRTIO0_IRQ => {
-            panic!("VIRTIO IRQ");
+            disk_intr();
         }
         _ => {}
     }
@@ -112,12 +161,48 @@ fn handle_external_interrupt() {
 
 fn tick() {
     let mut tick = TICK.lock();
-    *tick += 1;
+    // count ticks by mtime, hart 0 misses ticks while idle without tick
+    // and takes extra timer interrupts when kicked
+    #[cfg(feature = "tickless")]
+    {
+        *tick = cmp::max(*tick, mtime() / TIMER_INTERVAL);
+    }
+    #[cfg(not(feature = "tickless"))]
+    {
+        *tick += 1;
+    }
+    clock_tick(*tick);
+    drop(tick);
+    get_scheduler().wakeup(tick_chan());
+}
+
+/// sleep channel of processes waiting for ticks
+fn tick_chan() -> usize {
+    &*TICK as *const SpinLock<u64> as usize
+}
+
+/// Return the number of ticks since boot
+pub fn uptime() -> u64 {
+    *TICK.lock()
+}
+
+/// Block the running process until n ticks have passed
+pub fn sleep_ticks(n: u64) {
+    let mut tick = TICK.lock();
+    let tick0 = *tick;
+    while *tick - tick0 < n {
+        tick = sleep(tick_chan(), &TICK, tick);
+    }
 }
 
 fn handle_software_interrupt() {
+    // the panicked hart kicks the others to stop them
+    if panicked() {
+        halt();
+    }
     if get_cpuid() == 0 {
         tick();
+        run_timers();
     }
 
     let mut sip = Sip::from_read();
@@ -161,16 +246,17 @@ pub fn kerneltrap() {
         Some(x) if x == Interrupt::SupervisorSoftware as u64 => {
             let proc = get_proc();
             unsafe {
-                if !proc.is_null() && (*proc).state == ProcState::RUNNING {
+                if !proc.is_null()
+                    && (*proc).state == ProcState::RUNNING
+                    && quantum_expired(&mut *proc)
+                {
                     yield_proc();
                 }
             }
         }
         None => {
-            let scause = Scause::from_read().bits();
-            let sepc = Sepc::from_read().bits();
-            let stval = Stval::from_read().bits();
-            println!("scause {:x} sepc={:x} stval={:x}", scause, sepc, stval);
+            // kernelvec catches faults in the kernel stack guard page
+            save_trap();
             panic!("kerneltrap");
         }
         _ => (),
@@ -180,6 +266,59 @@ pub fn kerneltrap() {
     sstatus.write();
 }
 
+/// The trap CSRs of a kernel trap, printed by the panic handler
+#[derive(Clone, Copy)]
+pub struct TrapInfo {
+    pub scause: u64,
+    pub sepc: u64,
+    pub stval: u64,
+}
+
+/// Save the trap CSRs in this hart's Cpu before panic
+fn save_trap() -> TrapInfo {
+    let trap = TrapInfo {
+        scause: Scause::from_read().bits(),
+        sepc: Sepc::from_read().bits(),
+        stval: Stval::from_read().bits(),
+    };
+    get_cpu().trap = Some(trap);
+    trap
+}
+
+fn is_page_fault(scause: &Scause) -> bool {
+    let code = scause.get_code();
+    !scause.is_interrupt()
+        && (code == Exception::InstructionPageFault as u64
+            || code == Exception::LoadPageFault as u64
+            || code == Exception::StorePageFault as u64)
+}
+
+/// kernelvec jumps here, on TRAP_STACK, when a page fault happens
+/// in the guard page of the kernel stack in use.
+/// The overflowing code may hold the UART or heap lock, so the report
+/// goes through RawUart and does not allocate.
+#[no_mangle]
+pub fn kernel_stack_overflow(sp: u64) -> ! {
+    save_trap();
+    let _ = writeln!(RawUart, "kernel stack overflow at sp={:x}", sp);
+    let proc = get_cpu().proc;
+    if proc.is_null() {
+        let _ = writeln!(RawUart, "hart {} has no process", get_cpuid());
+    } else {
+        let proc = unsafe { &*proc };
+        let kstack = proc.kstack;
+        let _ = writeln!(
+            RawUart,
+            "pid {} stack {:x}-{:x} guard {:x}",
+            proc.pid,
+            kstack,
+            kstack + KSTACK_SIZE,
+            kstack_guard(kstack)
+        );
+    }
+    panic!("kernel stack overflow");
+}
+
 pub unsafe fn usertrapret() {
     let proc = get_proc() as *mut Box<Proc>;
 
@@ -197,7 +336,7 @@ pub unsafe fn usertrapret() {
     let trapframe = (*proc).trapframe.as_mut();
     let satp = Satp::from_read();
     trapframe.kernel_satp = satp.bits(); // kernel page table
-    trapframe.kernel_sp = (*proc).kstack + PAGESIZE; // process's kernel stack
+    trapframe.kernel_sp = (*proc).kstack + KSTACK_SIZE; // process's kernel stack
     trapframe.kernel_trap = usertrap as u64;
     trapframe.kernel_hartid = tp::read(); // hartid for cpuid()
 
@@ -216,7 +355,7 @@ pub unsafe fn usertrapret() {
     // tell trampoline.S the user page table to switch to.
     let mut satp = Satp::from_bits(0);
     let pagetable = (*proc).pagetable.as_mut();
-    satp.set_mode(SatpMode::ModeSv39);
+    satp.set_mode(SATP_MODE);
     satp.set_addr(pagetable as *const _ as u64);
     let satp = satp.bits();
 
@@ -252,7 +391,9 @@ pub fn usertrap() {
     if scause.is_interrupt() {
         match interrupt_handler() {
             Some(x) if x == Interrupt::SupervisorSoftware as u64 => {
-                yield_proc();
+                if quantum_expired(unsafe { &mut *proc }) {
+                    yield_proc();
+                }
             }
             None => {
                 // TODO just kill process, don't panic
@@ -281,6 +422,14 @@ pub fn usertrap() {
 
                 syscall();
             }
+            _ if is_page_fault(&scause) => {
+                let va = Stval::from_read().bits();
+                if !handle_page_fault(unsafe { &mut *proc }, va, code) {
+                    let pid = unsafe { (*proc).pid };
+                    println!("usertrap(): page fault at {:x} pid {}, killed", va, pid);
+                    exit_proc();
+                }
+            }
             _ => {
                 panic!("usertrap: unexpected exception");
             }
@@ -1,14 +1,14 @@
 use crate::console::CONSOLE;
 use crate::memorylayout;
 use crate::param::UART_TX_BUF_SIZE;
+use crate::spinlock::SpinLock;
 
 use bitflags::bitflags;
 use lazy_static::lazy_static;
-use spin::Mutex;
 use volatile_register::RW;
 
 lazy_static! {
-    pub static ref UART: Mutex<Uart> = Mutex::new(Uart::new());
+    pub static ref UART: SpinLock<Uart> = SpinLock::new("uart", Uart::new());
 }
 
 bitflags! {
@@ -52,6 +52,16 @@ pub struct Uart {
     p: &'static mut UartRegister,
 }
 
+/// Write c polling the uart registers, without the UART lock.
+/// Only for the panic path, where the lock may never be released.
+pub fn putc_raw(c: u8) {
+    let p = unsafe { &*(memorylayout::UART0 as *const UartRegister) };
+    while (p.lsr.read() & 0x20) == 0 {}
+    unsafe {
+        p.thr.write(c);
+    }
+}
+
 impl Uart {
     fn new() -> Self {
         let mut uart = Uart {
@@ -156,6 +156,13 @@ impl VirtioHeader {
             self.queue_notify.write(v.into());
         }
     }
+
+    /// Acknowledge the pending interrupts so the device can raise new ones
+    pub fn ack_interrupt(&mut self) {
+        unsafe {
+            self.interrupt_ack.write(self.interrupt_status.read() & 0x3);
+        }
+    }
 }
 
 bitflags! {
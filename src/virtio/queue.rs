@@ -4,7 +4,8 @@ use super::header::VirtioHeader;
 use super::Error;
 use bitflags::bitflags;
 
-use core::ptr::NonNull;
+use core::ptr::{read_volatile, write_volatile, NonNull};
+use rv64::asm::sync_synchronize;
 
 pub const MAX_QUEUE_SIZE: usize = 32768;
 
@@ -67,6 +68,27 @@ impl VirtioQueue {
             used,
         })
     }
+
+    /// Descriptor i of the queue
+    pub fn desc_mut(&mut self, i: u16) -> &mut Descriptor {
+        assert!(i < self.size, "virtio queue: descriptor {}", i);
+        unsafe { &mut *self.desc.as_ptr().add(i as usize) }
+    }
+
+    /// Make the descriptor chain starting at head available to the device
+    pub fn push_avail(&mut self, head: u16) {
+        let avail = unsafe { self.avail.as_mut() };
+        avail.ring[(avail.idx % self.size) as usize] = head;
+        // the device must see the ring entry before the index
+        sync_synchronize();
+        unsafe { write_volatile(&mut avail.idx, avail.idx.wrapping_add(1)) };
+        sync_synchronize();
+    }
+
+    /// Index of the used ring, advanced by the device as it completes requests
+    pub fn used_idx(&self) -> u16 {
+        unsafe { read_volatile(&self.used.as_ref().idx) }
+    }
 }
 
 // FIXME: expose interface instead of public access
@@ -1,4 +1 @@
-pub mod addr;
-pub mod page_flag;
-pub mod page_table;
-pub mod page_table_walker;
+pub use rrxv6_common::vm::{addr, page_flag, page_table, page_table_walker};
@@ -0,0 +1,192 @@
+//! Virtual memory areas of a process, created by mmap
+//!
+//! mmap only reserves the range in Proc::vmas, the pages are allocated
+//! and mapped by the page fault handler on first access.
+//! There is no file system yet, so only anonymous mappings are supported,
+//! file-backed mappings are listed as not yet supported in the README.
+//! Without fork nothing can share a mapping, so MAP_SHARED anonymous
+//! memory behaves like MAP_PRIVATE.
+
+use crate::kvm::{map_user_page, unmap_user_pages};
+use crate::memorylayout::MMAP_TOP;
+use crate::param::NVMA;
+use crate::proc::Proc;
+use crate::riscv::{Exception, PAGESIZE};
+use crate::vm::addr::{align_down, align_up, VirtAddr};
+use crate::vm::page_flag::PteFlag;
+use crate::vm::page_table::PageTable;
+
+use core::cmp;
+
+// prot of mmap
+pub const PROT_READ: u64 = 0x1;
+pub const PROT_WRITE: u64 = 0x2;
+pub const PROT_EXEC: u64 = 0x4;
+
+// flags of mmap
+pub const MAP_SHARED: u64 = 0x01;
+pub const MAP_PRIVATE: u64 = 0x02;
+pub const MAP_ANONYMOUS: u64 = 0x20;
+
+#[derive(Clone, Copy)]
+pub struct Vma {
+    /// first address, page aligned
+    pub start: u64,
+    /// end address, exclusive and page aligned
+    pub end: u64,
+    /// permission of the pages, PTE_USER is added when mapped
+    pub perm: PteFlag,
+}
+
+impl Vma {
+    pub fn npages(&self) -> u64 {
+        (self.end - self.start) / PAGESIZE
+    }
+
+    fn contains(&self, va: u64) -> bool {
+        self.start <= va && va < self.end
+    }
+}
+
+/// Reserve len bytes of anonymous memory below the lowest area,
+/// return the start address or an error.
+/// The addr hint is ignored, fd and off are not used by anonymous mappings.
+pub fn mmap(proc: &mut Proc, len: u64, prot: u64, flags: u64) -> Result<u64, &'static str> {
+    if len == 0 || prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0 {
+        return Err("mmap: invalid argument");
+    }
+    let sharing = flags & (MAP_SHARED | MAP_PRIVATE);
+    if sharing != MAP_SHARED && sharing != MAP_PRIVATE {
+        return Err("mmap: one of MAP_SHARED and MAP_PRIVATE");
+    }
+    if flags & MAP_ANONYMOUS == 0 {
+        return Err("mmap: file mappings are not supported");
+    }
+    if proc.vmas.len() >= NVMA {
+        return Err("mmap: too many areas");
+    }
+
+    let mut perm = PteFlag::empty();
+    if prot & PROT_READ != 0 {
+        perm |= PteFlag::PTE_READ;
+    }
+    if prot & PROT_WRITE != 0 {
+        // write-only pages are reserved in riscv
+        perm |= PteFlag::PTE_READ | PteFlag::PTE_WRITE;
+    }
+    if prot & PROT_EXEC != 0 {
+        perm |= PteFlag::PTE_EXEC;
+    }
+
+    let top = proc
+        .vmas
+        .iter()
+        .map(|vma| vma.start)
+        .fold(MMAP_TOP, cmp::min);
+    let len = page_len(len).ok_or("mmap: out of address space")?;
+    if top < len || top - len < align_up(proc.memory_size, PAGESIZE) {
+        return Err("mmap: out of address space");
+    }
+    let start = top - len;
+    proc.vmas.push(Vma {
+        start,
+        end: top,
+        perm,
+    });
+    Ok(start)
+}
+
+/// len rounded up to whole pages, None if that overflows
+fn page_len(len: u64) -> Option<u64> {
+    len.checked_add(PAGESIZE - 1)
+        .map(|len| align_down(len, PAGESIZE))
+}
+
+/// Remove the mappings in len bytes from addr, which must be page aligned.
+/// Areas partly in the range are shrunk or split, nothing is removed
+/// if splitting an area needs more than NVMA areas.
+pub fn munmap(proc: &mut Proc, addr: u64, len: u64) -> Result<(), &'static str> {
+    if addr % PAGESIZE != 0 || len == 0 {
+        return Err("munmap: invalid argument");
+    }
+    let end = addr
+        .checked_add(page_len(len).ok_or("munmap: overflow")?)
+        .ok_or("munmap: overflow")?;
+    // areas do not overlap, so at most one is split
+    let splits = proc
+        .vmas
+        .iter()
+        .any(|vma| vma.start < addr && end < vma.end);
+    if splits && proc.vmas.len() >= NVMA {
+        return Err("munmap: too many areas");
+    }
+    let page_table = unsafe { proc.pagetable.as_mut() };
+
+    let mut i = 0;
+    while i < proc.vmas.len() {
+        let vma = proc.vmas[i];
+        let from = cmp::max(vma.start, addr);
+        let to = cmp::min(vma.end, end);
+        if from >= to {
+            i += 1;
+            continue;
+        }
+        unmap_user_pages(page_table, VirtAddr::new(from), (to - from) / PAGESIZE);
+
+        match (vma.start < from, to < vma.end) {
+            (false, false) => {
+                proc.vmas.swap_remove(i);
+                continue;
+            }
+            (true, false) => proc.vmas[i].end = from,
+            (false, true) => proc.vmas[i].start = to,
+            (true, true) => {
+                // a hole in the middle splits the area
+                proc.vmas[i].end = from;
+                proc.vmas.push(Vma { start: to, ..vma });
+            }
+        }
+        i += 1;
+    }
+    Ok(())
+}
+
+/// Populate the page of va if it lies in an area allowing access,
+/// the scause exception code tells the access.
+/// Return false if the fault is not for a mapped area.
+pub fn handle_page_fault(proc: &mut Proc, va: u64, code: u64) -> bool {
+    let need = match code {
+        x if x == Exception::InstructionPageFault as u64 => PteFlag::PTE_EXEC,
+        x if x == Exception::LoadPageFault as u64 => PteFlag::PTE_READ,
+        x if x == Exception::StorePageFault as u64 => PteFlag::PTE_WRITE,
+        _ => return false,
+    };
+    let page_table = unsafe { proc.pagetable.as_mut() };
+    match proc.vmas.iter().find(|vma| vma.contains(va)) {
+        Some(vma) if vma.perm.contains(need) => populate(page_table, va, vma.perm).is_ok(),
+        _ => false,
+    }
+}
+
+/// Populate the pages of the areas in len bytes from addr,
+/// before the kernel copies from or to them.
+pub fn fault_in(proc: &mut Proc, addr: u64, len: u64) {
+    let end = addr.saturating_add(len);
+    let page_table = unsafe { proc.pagetable.as_mut() };
+    for vma in proc.vmas.iter() {
+        let mut va = align_down(cmp::max(vma.start, addr), PAGESIZE);
+        while va < cmp::min(vma.end, end) {
+            // a page already mapped fails with remap
+            let _ = populate(page_table, va, vma.perm);
+            va += PAGESIZE;
+        }
+    }
+}
+
+fn populate(page_table: &mut PageTable, va: u64, perm: PteFlag) -> Result<(), &'static str> {
+    if perm.is_empty() {
+        // PROT_NONE
+        return Err("populate: no access");
+    }
+    map_user_page(page_table, VirtAddr::new(align_down(va, PAGESIZE)), perm)
+}
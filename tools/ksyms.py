@@ -0,0 +1,101 @@
+#!/usr/bin/env python3
+"""Embed the function symbols of the kernel into its .ksyms section.
+
+Run after linking: python3 tools/ksyms.py <kernel elf>
+The table is written in place, the section keeps its size so no address moves.
+Set NM to the nm of the riscv toolchain, it has to demangle rust symbols.
+
+Table layout, little endian, read by src/ksyms.rs:
+    b"KSYM", count: u32
+    count entries sorted by address: addr: u64, size: u32, name offset: u32
+    the names, nul-terminated, offsets counted from the start of the table
+"""
+
+import os
+import re
+import struct
+import subprocess
+import sys
+
+MAGIC = b"KSYM"
+HEADER = struct.Struct("<4sI")
+ENTRY = struct.Struct("<QII")
+
+
+def find_section(elf, name):
+    """Return (file offset, size) of the named section of a 64-bit little endian ELF"""
+    if elf[:4] != b"\x7fELF" or elf[4] != 2 or elf[5] != 1:
+        sys.exit("ksyms: not a 64-bit little endian ELF")
+    (shoff,) = struct.unpack_from("<Q", elf, 0x28)
+    shentsize, shnum, shstrndx = struct.unpack_from("<HHH", elf, 0x3A)
+
+    def header(i):
+        # sh_name, sh_offset and sh_size
+        base = shoff + i * shentsize
+        (sh_name,) = struct.unpack_from("<I", elf, base)
+        sh_offset, sh_size = struct.unpack_from("<QQ", elf, base + 0x18)
+        return sh_name, sh_offset, sh_size
+
+    _, strtab, _ = header(shstrndx)
+    for i in range(shnum):
+        sh_name, offset, size = header(i)
+        end = elf.index(b"\0", strtab + sh_name)
+        if elf[strtab + sh_name:end] == name:
+            return offset, size
+    sys.exit("ksyms: no {} section".format(name.decode()))
+
+
+def read_symbols(kernel):
+    """Return sorted [(addr, size, name)] of the functions in kernel"""
+    nm = os.environ.get("NM", "riscv64-unknown-elf-nm")
+    out = subprocess.run(
+        [nm, "-n", "-S", "-C", "--defined-only", kernel],
+        check=True, stdout=subprocess.PIPE, universal_newlines=True,
+    ).stdout
+    symbols = {}
+    for line in out.splitlines():
+        fields = line.split(maxsplit=3)
+        if len(fields) == 4:
+            addr, size, kind, name = fields
+        elif len(fields) == 3:
+            (addr, kind, name), size = fields, "0"
+        else:
+            continue
+        if kind not in "tT":
+            continue
+        # drop the hash of rust symbols
+        name = re.sub(r"::h[0-9a-f]{16}$", "", name)
+        symbols.setdefault(int(addr, 16), (int(size, 16), name))
+    return [(addr, size, name) for addr, (size, name) in sorted(symbols.items())]
+
+
+def build_table(symbols):
+    names = bytearray()
+    entries = bytearray()
+    name_base = HEADER.size + ENTRY.size * len(symbols)
+    for addr, size, name in symbols:
+        entries += ENTRY.pack(addr, size, name_base + len(names))
+        names += name.encode() + b"\0"
+    return HEADER.pack(MAGIC, len(symbols)) + entries + names
+
+
+def main():
+    if len(sys.argv) != 2:
+        sys.exit("usage: ksyms.py <kernel>")
+    kernel = sys.argv[1]
+    with open(kernel, "rb") as f:
+        elf = f.read()
+    offset, size = find_section(elf, b".ksyms")
+    symbols = read_symbols(kernel)
+    table = build_table(symbols)
+    if len(table) > size:
+        sys.exit("ksyms: table of {} bytes exceeds .ksyms of {} bytes, raise KSYMS_SIZE"
+                 .format(len(table), size))
+    with open(kernel, "r+b") as f:
+        f.seek(offset)
+        f.write(table + bytes(size - len(table)))
+    print("ksyms: {} symbols, {} of {} bytes".format(len(symbols), len(table), size))
+
+
+if __name__ == "__main__":
+    main()